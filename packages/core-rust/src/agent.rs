@@ -0,0 +1,455 @@
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use meshclaw_core::approval::{ApprovalGate, ApprovalRequest, Decision};
+use meshclaw_core::auction::Bid;
+use meshclaw_core::cache::{CachedResult, Provenance, ResultCache};
+use meshclaw_core::inference::{GenerationParams, InferenceError, InferenceRouter, OutputFormat, TokenUsage, DEFAULT_CAPABILITY};
+use meshclaw_core::ledger::{Ledger, Receipt};
+use meshclaw_core::plugins::PluginHost;
+use meshclaw_core::quota::QuotaTracker;
+use meshclaw_core::sync::{MemorySync, SyncMessage};
+use meshclaw_core::tools::ToolRegistry;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Executes `Delegate` tasks assigned to this node. LLM capabilities stream
+/// output back as `task-chunk` messages followed by a `task-result`; plugin
+/// and `tool:` capabilities reply with a single `task-result` carrying
+/// structured output. LLM payloads are validated as `GenerationParams`
+/// before anything runs; deterministic LLM requests are answered from and
+/// stored in the result cache when one is configured. Capabilities covered by
+/// the approval gate are held as `awaiting-approval` until an operator decides.
+/// Requesters over their quota are refused. Every result is recorded in the
+/// ledger and the quota tracker, with the tokens the backend reported, and
+/// carries a receipt signed with this node's key.
+#[derive(Clone)]
+pub struct LocalAgent {
+    inference: Arc<InferenceRouter>,
+    tools: Arc<ToolRegistry>,
+    plugins: Arc<PluginHost>,
+    memory: Arc<MemorySync>,
+    cache: Option<Arc<ResultCache>>,
+    ledger: Arc<Ledger>,
+    quotas: Arc<QuotaTracker>,
+    approvals: Arc<ApprovalGate>,
+    keypair: Keypair,
+    task_tx: tokio::sync::mpsc::Sender<SyncMessage>,
+    gateway_tx: tokio::sync::broadcast::Sender<serde_json::Value>,
+    serving: Arc<AtomicU32>,
+}
+
+impl LocalAgent {
+    pub fn new(
+        inference: Arc<InferenceRouter>,
+        tools: Arc<ToolRegistry>,
+        plugins: Arc<PluginHost>,
+        memory: Arc<MemorySync>,
+        cache: Option<Arc<ResultCache>>,
+        ledger: Arc<Ledger>,
+        quotas: Arc<QuotaTracker>,
+        approvals: Arc<ApprovalGate>,
+        keypair: Keypair,
+        task_tx: tokio::sync::mpsc::Sender<SyncMessage>,
+        gateway_tx: tokio::sync::broadcast::Sender<serde_json::Value>,
+    ) -> Self {
+        LocalAgent {
+            inference,
+            tools,
+            plugins,
+            memory,
+            cache,
+            ledger,
+            quotas,
+            approvals,
+            keypair,
+            task_tx,
+            gateway_tx,
+            serving: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Number of tasks currently running on this node.
+    pub fn queue_depth(&self) -> u32 {
+        self.serving.load(Ordering::Relaxed)
+    }
+
+    /// Offer for a call for proposals, or `None` if we would refuse the requester.
+    /// The ETA assumes queued tasks take as long as our past average; the asking
+    /// price comes from `MESHCLAW_BID_COST` (default 0).
+    pub fn bid(&self, requester_id: &str) -> Option<Bid> {
+        if !self.ledger.permits(requester_id) || self.quotas.check(requester_id).is_err() {
+            return None;
+        }
+        let queue_depth = self.queue_depth();
+        let per_task = self.ledger.avg_served_latency_ms().unwrap_or(1000);
+        Some(Bid {
+            bidder_id: PeerId::from(self.keypair.public()).to_string(),
+            eta_ms: per_task * (queue_depth as u64 + 1),
+            queue_depth,
+            cost: std::env::var("MESHCLAW_BID_COST").ok().and_then(|c| c.parse().ok()).unwrap_or(0.0),
+        })
+    }
+
+    /// Runs a task delegated by `source`, the peer that published it. Quotas
+    /// and reciprocity are checked for `source` rather than the `requesterId`
+    /// it declares.
+    pub fn run(&self, msg: SyncMessage, source: &str) {
+        let (task_id, requester_id, prompt, capability, payload) = match msg {
+            SyncMessage::Delegate { task_id, requester_id, task_desc, capability, payload, .. } => {
+                (task_id, requester_id, task_desc, capability.unwrap_or_else(|| DEFAULT_CAPABILITY.to_string()), payload)
+            },
+            _ => return,
+        };
+        if !self.ledger.permits(source) {
+            eprintln!("🦞 Local Agent: Refusing task '{}' from {}: it has not reciprocated", task_id, source);
+            self.ledger.record_refused(source);
+            let error = serde_json::json!({ "kind": "refused", "message": "requester has not reciprocated enough work" });
+            self.reject(task_id, requester_id, error);
+            return;
+        }
+        if let Err(e) = self.quotas.admit(source) {
+            eprintln!("🦞 Local Agent: Refusing task '{}' from {}: {}", task_id, requester_id, e);
+            self.reject(task_id, requester_id, serde_json::json!({ "kind": e.kind(), "message": e.to_string() }));
+            return;
+        }
+        let is_llm = !self.plugins.provides(&capability) && !capability.starts_with("tool:");
+        let (params, prompt) = if is_llm {
+            match self.prepare_prompt(prompt, &payload) {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    eprintln!("🦞 Local Agent: Rejecting task '{}': {}", task_id, e);
+                    self.reject(task_id, requester_id, serde_json::json!({ "kind": e.kind(), "message": e.to_string() }));
+                    return;
+                }
+            }
+        } else {
+            (GenerationParams::default(), prompt)
+        };
+        let cache_key = match &self.cache {
+            Some(_) if is_llm => self
+                .inference
+                .resolve(&capability, &params)
+                .ok()
+                .and_then(|route| ResultCache::key(&route, &prompt, &params)),
+            _ => None,
+        };
+        if let Some(hit) = cache_key.as_ref().and_then(|key| self.cache.as_ref()?.get(key)) {
+            println!("🦞 Local Agent: Answering task '{}' from cache", task_id);
+            self.reply_cached(task_id, requester_id, source.to_string(), hit);
+            return;
+        }
+        let source = source.to_string();
+        if self.approvals.requires(&capability) {
+            self.hold(Task { task_id, requester_id, source, prompt, capability, payload, params, cache_key });
+            return;
+        }
+        self.execute(Task { task_id, requester_id, source, prompt, capability, payload, params, cache_key });
+    }
+
+    fn execute(&self, task: Task) {
+        let Task { task_id, requester_id, source, prompt, capability, payload, params, cache_key } = task;
+        println!("🦞 Local Agent: Processing task '{}' via {} [{}]", prompt, capability, task_id);
+
+        let started = Instant::now();
+        let ledger = self.ledger.clone();
+        let quotas = self.quotas.clone();
+        let keypair = self.keypair.clone();
+        let inference = self.inference.clone();
+        let task_tx = self.task_tx.clone();
+        let serving = self.serving.clone();
+        serving.fetch_add(1, Ordering::Relaxed);
+
+        // Plugins take precedence, so a plugin can also provide a `tool:` capability.
+        if self.plugins.provides(&capability) {
+            let plugins = self.plugins.clone();
+            tokio::spawn(async move {
+                let outcome = plugins
+                    .call(&capability, payload)
+                    .await
+                    .map_err(|e| serde_json::json!({ "kind": e.kind(), "message": e.to_string() }));
+                serving.fetch_sub(1, Ordering::Relaxed);
+                let result = settle(&ledger, &quotas, &keypair, &source, started, None, structured_result(task_id, requester_id, outcome));
+                let _ = task_tx.send(result).await;
+            });
+            return;
+        }
+
+        if let Some(tool) = capability.strip_prefix("tool:").map(|t| t.to_string()) {
+            let tools = self.tools.clone();
+            tokio::spawn(async move {
+                let outcome = tools
+                    .call(&tool, payload)
+                    .await
+                    .map_err(|e| serde_json::json!({ "kind": e.kind(), "message": e.to_string() }));
+                serving.fetch_sub(1, Ordering::Relaxed);
+                let result = settle(&ledger, &quotas, &keypair, &source, started, None, structured_result(task_id, requester_id, outcome));
+                let _ = task_tx.send(result).await;
+            });
+            return;
+        }
+
+        let cache = self.cache.clone();
+        tokio::spawn(async move {
+            let (token_tx, mut token_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+
+            let chunk_tx = task_tx.clone();
+            let chunk_task_id = task_id.clone();
+            let chunk_requester_id = requester_id.clone();
+            let forwarder = tokio::spawn(async move {
+                let mut seq = 0;
+                while let Some(delta) = token_rx.recv().await {
+                    let _ = chunk_tx.send(SyncMessage::TaskChunk {
+                        task_id: chunk_task_id.clone(),
+                        requester_id: chunk_requester_id.clone(),
+                        seq,
+                        delta,
+                    }).await;
+                    seq += 1;
+                }
+                seq
+            });
+
+            let outcome = inference.generate_stream(&capability, &prompt, &params, token_tx).await;
+            let chunks = forwarder.await.unwrap_or(0);
+            serving.fetch_sub(1, Ordering::Relaxed);
+
+            // JSON output is also returned parsed, so requesters get structured data.
+            let outcome = outcome.and_then(|resp| match params.format {
+                OutputFormat::Json => serde_json::from_str(&resp.text)
+                    .map(|output| (resp, Some(output)))
+                    .map_err(|e| InferenceError::InvalidResponse(format!("output is not valid JSON: {}", e))),
+                OutputFormat::Text => Ok((resp, None)),
+            });
+            let mut usage = None;
+            let result = match outcome {
+                Ok((resp, output)) => {
+                    println!("🦞 Local Agent: Task '{}' completed", task_id);
+                    usage = resp.usage;
+                    if let (Some(cache), Some(key)) = (&cache, cache_key) {
+                        let cached = CachedResult {
+                            text: resp.text.clone(),
+                            model: Some(resp.model.clone()),
+                            output: output.clone(),
+                            provenance: Provenance {
+                                key,
+                                provider_id: PeerId::from(keypair.public()).to_string(),
+                                task_id: task_id.clone(),
+                                created_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+                                receipt: None,
+                            },
+                        };
+                        if let Err(e) = cache.put(&cached) {
+                            eprintln!("🦞 Local Agent: Failed to cache result of '{}': {}", task_id, e);
+                        }
+                    }
+                    SyncMessage::TaskResult {
+                        task_id,
+                        requester_id,
+                        status: "completed".to_string(),
+                        chunks,
+                        model: Some(resp.model),
+                        output,
+                        error: None,
+                        receipt: None,
+                        provenance: None,
+                    }
+                },
+                Err(e) => {
+                    eprintln!("🦞 Local Agent: Task '{}' failed: {}", task_id, e);
+                    SyncMessage::TaskResult {
+                        task_id,
+                        requester_id,
+                        status: "failed".to_string(),
+                        chunks,
+                        model: None,
+                        output: None,
+                        error: Some(serde_json::json!({ "kind": e.kind(), "message": e.to_string() })),
+                        receipt: None,
+                        provenance: None,
+                    }
+                }
+            };
+            let _ = task_tx.send(settle(&ledger, &quotas, &keypair, &source, started, usage, result)).await;
+        });
+    }
+
+    /// Parks a task until an operator approves it through the bridge, telling
+    /// both the requester and the local gateway that it is waiting.
+    fn hold(&self, task: Task) {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let request = ApprovalRequest {
+            approval_id: String::new(),
+            task_id: task.task_id.clone(),
+            requester_id: task.requester_id.clone(),
+            capability: task.capability.clone(),
+            task_desc: task.prompt.clone(),
+            payload: task.payload.clone(),
+            requested_at: now,
+            expires_at: now + self.approvals.timeout().as_secs(),
+        };
+        let (request, decision) = self.approvals.hold(request);
+        let approval_id = request.approval_id.clone();
+        println!("🦞 Local Agent: Task '{}' via {} is awaiting approval as {}", task.task_id, task.capability, approval_id);
+        let _ = self.gateway_tx.send(serde_json::json!({
+            "type": "event",
+            "method": "mesh:approval:request",
+            "params": request
+        }));
+
+        let agent = self.clone();
+        tokio::spawn(async move {
+            let _ = agent.task_tx.send(SyncMessage::TaskStatus {
+                task_id: task.task_id.clone(),
+                requester_id: task.requester_id.clone(),
+                status: "awaiting-approval".to_string(),
+            }).await;
+            let error = match tokio::time::timeout(agent.approvals.timeout(), decision).await {
+                Ok(Ok(Decision::Approved)) => {
+                    println!("🦞 Local Agent: Task '{}' approved", task.task_id);
+                    let _ = agent.gateway_tx.send(serde_json::json!({
+                        "type": "event",
+                        "method": "mesh:approval:resolved",
+                        "params": { "approvalId": approval_id, "taskId": task.task_id, "approved": true }
+                    }));
+                    agent.execute(task);
+                    return;
+                },
+                Ok(Ok(Decision::Rejected(reason))) => serde_json::json!({
+                    "kind": "rejected",
+                    "message": reason.unwrap_or_else(|| "rejected by the provider's operator".to_string())
+                }),
+                Ok(Err(_)) | Err(_) => {
+                    agent.approvals.expire(&approval_id);
+                    serde_json::json!({ "kind": "approval-timeout", "message": "no operator approved the task in time" })
+                }
+            };
+            eprintln!("🦞 Local Agent: Task '{}' not approved: {}", task.task_id, error["message"]);
+            let _ = agent.gateway_tx.send(serde_json::json!({
+                "type": "event",
+                "method": "mesh:approval:resolved",
+                "params": { "approvalId": approval_id, "taskId": task.task_id, "approved": false, "error": error }
+            }));
+            agent.reject(task.task_id, task.requester_id, error);
+        });
+    }
+
+    /// Validates an LLM payload and prepends the requested context keys to the prompt.
+    fn prepare_prompt(&self, prompt: String, payload: &serde_json::Value) -> Result<(GenerationParams, String), InferenceError> {
+        let params = GenerationParams::from_payload(payload)?;
+        let prompt = params.expand_prompt(&prompt, &self.memory)?;
+        Ok((params, prompt))
+    }
+
+    /// Streams a cached answer back as a single chunk.
+    fn reply_cached(&self, task_id: String, requester_id: String, source: String, hit: CachedResult) {
+        let started = Instant::now();
+        let ledger = self.ledger.clone();
+        let quotas = self.quotas.clone();
+        let keypair = self.keypair.clone();
+        let task_tx = self.task_tx.clone();
+        tokio::spawn(async move {
+            let mut chunks = 0;
+            if !hit.text.is_empty() {
+                let _ = task_tx.send(SyncMessage::TaskChunk {
+                    task_id: task_id.clone(),
+                    requester_id: requester_id.clone(),
+                    seq: 0,
+                    delta: hit.text,
+                }).await;
+                chunks = 1;
+            }
+            let result = SyncMessage::TaskResult {
+                task_id,
+                requester_id,
+                status: "completed".to_string(),
+                chunks,
+                model: hit.model,
+                output: hit.output,
+                error: None,
+                receipt: None,
+                provenance: Some(hit.provenance),
+            };
+            let _ = task_tx.send(settle(&ledger, &quotas, &keypair, &source, started, None, result)).await;
+        });
+    }
+
+    /// Fails a task without running it.
+    fn reject(&self, task_id: String, requester_id: String, error: serde_json::Value) {
+        let task_tx = self.task_tx.clone();
+        tokio::spawn(async move {
+            let _ = task_tx.send(structured_result(task_id, requester_id, Err(error))).await;
+        });
+    }
+}
+
+/// A delegated task that passed admission checks.
+struct Task {
+    task_id: String,
+    requester_id: String,
+    /// Peer that published the task; usage is booked to it.
+    source: String,
+    prompt: String,
+    capability: String,
+    payload: serde_json::Value,
+    params: GenerationParams,
+    cache_key: Option<String>,
+}
+
+/// Books a finished task to `source` in the ledger and quota tracker and
+/// attaches a signed receipt.
+fn settle(
+    ledger: &Ledger,
+    quotas: &QuotaTracker,
+    keypair: &Keypair,
+    source: &str,
+    started: Instant,
+    usage: Option<TokenUsage>,
+    mut result: SyncMessage,
+) -> SyncMessage {
+    if let SyncMessage::TaskResult { task_id, requester_id, status, receipt, .. } = &mut result {
+        let elapsed = started.elapsed();
+        let usage = usage.unwrap_or_default();
+        ledger.record_served(source, status == "completed", elapsed, usage.total());
+        quotas.record_work(source, usage.prompt_tokens, usage.completion_tokens, elapsed);
+        *receipt = Receipt::sign(keypair, task_id, requester_id, status, elapsed);
+    }
+    result
+}
+
+fn structured_result(
+    task_id: String,
+    requester_id: String,
+    outcome: Result<serde_json::Value, serde_json::Value>,
+) -> SyncMessage {
+    match outcome {
+        Ok(output) => {
+            println!("🦞 Local Agent: Task '{}' completed", task_id);
+            SyncMessage::TaskResult {
+                task_id,
+                requester_id,
+                status: "completed".to_string(),
+                chunks: 0,
+                model: None,
+                output: Some(output),
+                error: None,
+                receipt: None,
+                provenance: None,
+            }
+        },
+        Err(error) => {
+            eprintln!("🦞 Local Agent: Task '{}' failed: {}", task_id, error["message"]);
+            SyncMessage::TaskResult {
+                task_id,
+                requester_id,
+                status: "failed".to_string(),
+                chunks: 0,
+                model: None,
+                output: None,
+                error: Some(error),
+                receipt: None,
+                provenance: None,
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// How long a held task waits for an operator when not configured.
+pub const DEFAULT_APPROVAL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// An operator's answer to an approval request.
+#[derive(Debug, Clone)]
+pub enum Decision {
+    Approved,
+    Rejected(Option<String>),
+}
+
+/// A delegated task held until an operator decides on it. Task IDs are
+/// chosen by requesters, so operators answer by `approval_id`, which the
+/// gate assigns when the task is held.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApprovalRequest {
+    pub approval_id: String,
+    pub task_id: String,
+    pub requester_id: String,
+    pub capability: String,
+    pub task_desc: String,
+    pub payload: serde_json::Value,
+    pub requested_at: u64,
+    pub expires_at: u64,
+}
+
+/// Holds tasks for sensitive capabilities until they are approved or rejected
+/// through the bridge.
+pub struct ApprovalGate {
+    patterns: Vec<String>,
+    timeout: Duration,
+    pending: Mutex<HashMap<String, (ApprovalRequest, oneshot::Sender<Decision>)>>,
+    next_id: AtomicU64,
+}
+
+impl ApprovalGate {
+    pub fn new(patterns: Vec<String>, timeout: Duration) -> Self {
+        ApprovalGate { patterns, timeout, pending: Mutex::new(HashMap::new()), next_id: AtomicU64::new(1) }
+    }
+
+    /// `MESHCLAW_APPROVAL_REQUIRED` is a comma-separated list of capabilities
+    /// that need approval, where a trailing `*` matches a prefix (default
+    /// `tool:shell`; empty disables the gate). Held tasks are rejected after
+    /// `MESHCLAW_APPROVAL_TIMEOUT_SECS` (default 300).
+    pub fn from_env() -> Self {
+        let patterns = std::env::var("MESHCLAW_APPROVAL_REQUIRED")
+            .unwrap_or_else(|_| "tool:shell".to_string())
+            .split(',')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        let timeout = std::env::var("MESHCLAW_APPROVAL_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_APPROVAL_TIMEOUT);
+        ApprovalGate::new(patterns, timeout)
+    }
+
+    pub fn requires(&self, capability: &str) -> bool {
+        self.patterns.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => capability.starts_with(prefix),
+            None => p == capability,
+        })
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Registers a held task under a new approval ID, returning the request
+    /// with that ID and a receiver that resolves when an operator decides.
+    pub fn hold(&self, mut request: ApprovalRequest) -> (ApprovalRequest, oneshot::Receiver<Decision>) {
+        let (tx, rx) = oneshot::channel();
+        request.approval_id = format!("approval-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.pending.lock().unwrap().insert(request.approval_id.clone(), (request.clone(), tx));
+        (request, rx)
+    }
+
+    /// Resolves a held task. Returns false if no such approval is waiting.
+    pub fn decide(&self, approval_id: &str, decision: Decision) -> bool {
+        match self.pending.lock().unwrap().remove(approval_id) {
+            Some((_, tx)) => tx.send(decision).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Drops a held task whose wait timed out.
+    pub fn expire(&self, approval_id: &str) {
+        self.pending.lock().unwrap().remove(approval_id);
+    }
+
+    pub fn pending(&self) -> Vec<ApprovalRequest> {
+        let mut list: Vec<ApprovalRequest> = self.pending.lock().unwrap().values().map(|(r, _)| r.clone()).collect();
+        list.sort_by_key(|r| r.requested_at);
+        list
+    }
+}
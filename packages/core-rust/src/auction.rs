@@ -0,0 +1,72 @@
+use crate::scheduler::{PeerStats, Preferences};
+use crate::sync::SyncMessage;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long a requester collects bids when the task does not say.
+pub const DEFAULT_BID_WINDOW: Duration = Duration::from_secs(2);
+
+/// A provider's offer to run a task.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Bid {
+    pub bidder_id: String,
+    /// Estimated time until the result is delivered.
+    pub eta_ms: u64,
+    pub queue_depth: u32,
+    /// Price asked by the provider, in arbitrary units; 0 means free.
+    #[serde(default)]
+    pub cost: f64,
+}
+
+impl Bid {
+    /// Lower is better: expected time, inflated by cost and divided by how
+    /// reliable the bidder has been so far.
+    pub fn score(&self, stats: Option<&PeerStats>) -> f64 {
+        let success_rate = stats.map(|s| s.success_rate()).unwrap_or(0.5);
+        (self.eta_ms as f64 + 1.0) * (1.0 + self.cost.max(0.0)) / success_rate
+    }
+}
+
+/// Bids collected for one task until its deadline.
+pub struct Auction {
+    msg: SyncMessage,
+    bids: Vec<(PeerId, Bid)>,
+}
+
+impl Auction {
+    pub fn new(msg: SyncMessage) -> Self {
+        Auction { msg, bids: Vec::new() }
+    }
+
+    /// Keeps the latest bid per bidder.
+    pub fn record(&mut self, peer: PeerId, bid: Bid) {
+        self.bids.retain(|(p, _)| *p != peer);
+        self.bids.push((peer, bid));
+    }
+
+    pub fn msg(&self) -> &SyncMessage {
+        &self.msg
+    }
+
+    pub fn bids(&self) -> &[(PeerId, Bid)] {
+        &self.bids
+    }
+
+    /// Bidders from best to worst, skipping peers the requester wants to avoid.
+    pub fn ranked<'a>(&self, prefs: &Preferences, stats: impl Fn(&PeerId) -> Option<&'a PeerStats>) -> Vec<PeerId> {
+        let mut scored: Vec<(f64, PeerId)> = self
+            .bids
+            .iter()
+            .filter(|(peer, _)| !prefs.avoid.contains(&peer.to_string()))
+            .map(|(peer, bid)| (bid.score(stats(peer)), *peer))
+            .collect();
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.to_string().cmp(&b.1.to_string())));
+        scored.into_iter().map(|(_, peer)| peer).collect()
+    }
+
+    pub fn into_msg(self) -> SyncMessage {
+        self.msg
+    }
+}
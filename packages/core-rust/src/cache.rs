@@ -0,0 +1,163 @@
+use crate::inference::{GenerationParams, OutputFormat, Route};
+use crate::ledger::Receipt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Results kept when not configured.
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+/// How long a result is kept when not configured.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Where a cached answer came from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub key: String,
+    /// Node that ran the inference.
+    pub provider_id: String,
+    pub task_id: String,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<Receipt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedResult {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<serde_json::Value>,
+    pub provenance: Provenance,
+}
+
+/// Content-addressed store of deterministic LLM results. Keys are announced
+/// in the DHT as `cache:<key>` so other nodes can route identical requests here.
+/// Results older than `max_age`, and the oldest beyond `max_entries`, are
+/// evicted and their announcements withdrawn.
+pub struct ResultCache {
+    db: sled::Db,
+    /// Keys stored since the last announcement.
+    unannounced: Mutex<Vec<String>>,
+    max_entries: usize,
+    max_age: Duration,
+}
+
+impl ResultCache {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(path)?;
+        let db = sled::open(path)?;
+        // Everything already on disk is announced again after a restart.
+        let existing = db
+            .iter()
+            .keys()
+            .filter_map(|k| k.ok())
+            .map(|k| String::from_utf8_lossy(&k).into_owned())
+            .collect();
+        Ok(ResultCache {
+            db,
+            unannounced: Mutex::new(existing),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_age: DEFAULT_MAX_AGE,
+        })
+    }
+
+    /// Enabled by `MESHCLAW_RESULT_CACHE_DIR`; disabled when unset. Keeps at
+    /// most `MESHCLAW_RESULT_CACHE_MAX_ENTRIES` results (default 10000), each
+    /// for `MESHCLAW_RESULT_CACHE_MAX_AGE_SECS` (default 604800).
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let dir = match std::env::var("MESHCLAW_RESULT_CACHE_DIR") {
+            Ok(dir) => dir,
+            Err(_) => return Ok(None),
+        };
+        let mut cache = ResultCache::open(Path::new(&dir))?;
+        if let Some(max) = std::env::var("MESHCLAW_RESULT_CACHE_MAX_ENTRIES").ok().and_then(|v| v.parse().ok()) {
+            cache.max_entries = max;
+        }
+        if let Some(secs) = std::env::var("MESHCLAW_RESULT_CACHE_MAX_AGE_SECS").ok().and_then(|v| v.parse().ok()) {
+            cache.max_age = Duration::from_secs(secs);
+        }
+        Ok(Some(cache))
+    }
+
+    /// Cache key for a request served by `route`, or `None` unless it is
+    /// deterministic (temperature 0). The key covers the backend, model and
+    /// options the capability resolves to, so nodes that map a capability to
+    /// different models never share answers. `prompt` must already include
+    /// any attached context.
+    pub fn key(route: &Route, prompt: &str, params: &GenerationParams) -> Option<String> {
+        if params.temperature != Some(0.0) {
+            return None;
+        }
+        let canonical = serde_json::json!({
+            "backend": route.backend.name(),
+            "model": route.model,
+            "options": route.options,
+            "prompt": prompt,
+            "system": params.system,
+            "maxTokens": params.max_tokens,
+            "json": params.format == OutputFormat::Json
+        });
+        let digest = Sha256::digest(canonical.to_string().as_bytes());
+        Some(digest.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// The result stored under `key`, unless it is older than the maximum age.
+    pub fn get(&self, key: &str) -> Option<CachedResult> {
+        let bytes = self.db.get(key).ok()??;
+        let result: CachedResult = serde_json::from_slice(&bytes).ok()?;
+        let cutoff = now_secs().saturating_sub(self.max_age.as_secs());
+        (result.provenance.created_at >= cutoff).then_some(result)
+    }
+
+    pub fn put(&self, result: &CachedResult) -> anyhow::Result<()> {
+        let key = result.provenance.key.clone();
+        let previous = self.db.insert(key.as_bytes(), serde_json::to_vec(result)?)?;
+        if previous.is_none() {
+            self.unannounced.lock().unwrap().push(key);
+        }
+        Ok(())
+    }
+
+    /// Keys that still need a DHT provider record.
+    pub fn take_unannounced(&self) -> Vec<String> {
+        std::mem::take(&mut *self.unannounced.lock().unwrap())
+    }
+
+    /// Removes results older than the maximum age, then the oldest beyond the
+    /// maximum count. Returns their keys, whose provider records have to be
+    /// withdrawn.
+    pub fn evict(&self) -> anyhow::Result<Vec<String>> {
+        let cutoff = now_secs().saturating_sub(self.max_age.as_secs());
+        let mut entries: Vec<(u64, String)> = Vec::new();
+        for entry in self.db.iter() {
+            let (key, bytes) = entry?;
+            // Unreadable entries count as oldest.
+            let created_at = serde_json::from_slice::<CachedResult>(&bytes).map(|r| r.provenance.created_at).unwrap_or(0);
+            entries.push((created_at, String::from_utf8_lossy(&key).into_owned()));
+        }
+        entries.sort();
+        let excess = entries.len().saturating_sub(self.max_entries);
+        let evicted: Vec<String> = entries
+            .into_iter()
+            .enumerate()
+            .filter(|(i, (created_at, _))| *i < excess || *created_at < cutoff)
+            .map(|(_, (_, key))| key)
+            .collect();
+        for key in &evicted {
+            self.db.remove(key.as_bytes())?;
+        }
+        if !evicted.is_empty() {
+            self.unannounced.lock().unwrap().retain(|key| !evicted.contains(key));
+        }
+        Ok(evicted)
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+}
@@ -0,0 +1,112 @@
+use crate::inference::InferenceRouter;
+use crate::plugins::PluginHost;
+use crate::tools::ToolRegistry;
+use crate::vector_db::VectorDb;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CapabilityInfo {
+    pub name: String,
+    pub metadata: serde_json::Value,
+}
+
+/// Probes what this node can actually serve right now: installed models on
+/// each inference backend, registered tools, loaded plugins and the local
+/// vector store.
+pub async fn probe(
+    inference: &InferenceRouter,
+    tools: &ToolRegistry,
+    plugins: &PluginHost,
+    vector_db: &VectorDb,
+) -> Vec<CapabilityInfo> {
+    let mut found = Vec::new();
+
+    for (name, model) in inference.probe().await {
+        found.push(CapabilityInfo {
+            name,
+            metadata: serde_json::to_value(&model).unwrap_or_default(),
+        });
+    }
+
+    for name in tools.names() {
+        if let Some(tool) = tools.get(&name) {
+            found.push(CapabilityInfo {
+                name: format!("tool:{}", name),
+                metadata: serde_json::json!({
+                    "description": tool.description(),
+                    "inputSchema": tool.input_schema()
+                }),
+            });
+        }
+    }
+
+    for (name, plugin) in plugins.capabilities() {
+        found.push(CapabilityInfo {
+            name,
+            metadata: serde_json::json!({ "plugin": plugin }),
+        });
+    }
+
+    match vector_db.row_count().await {
+        Ok(rows) => found.push(CapabilityInfo {
+            name: "memory:lancedb".to_string(),
+            metadata: serde_json::json!({ "rows": rows }),
+        }),
+        Err(e) => eprintln!("VectorDb unavailable: {}", e),
+    }
+
+    found
+}
+
+#[derive(Debug, Default)]
+pub struct CapabilityChanges {
+    pub announced: Vec<CapabilityInfo>,
+    pub withdrawn: Vec<String>,
+}
+
+/// The capabilities this node currently advertises.
+#[derive(Debug, Default)]
+pub struct CapabilitySet {
+    advertised: HashMap<String, serde_json::Value>,
+}
+
+impl CapabilitySet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the advertised set with a fresh probe result and reports which
+    /// capabilities are new or have changed metadata, and which disappeared.
+    pub fn apply(&mut self, probed: Vec<CapabilityInfo>) -> CapabilityChanges {
+        let mut changes = CapabilityChanges::default();
+        let mut next = HashMap::new();
+        for cap in probed {
+            if self.advertised.get(&cap.name) != Some(&cap.metadata) {
+                changes.announced.push(cap.clone());
+            }
+            next.insert(cap.name, cap.metadata);
+        }
+        for name in self.advertised.keys() {
+            if !next.contains_key(name) {
+                changes.withdrawn.push(name.clone());
+            }
+        }
+        self.advertised = next;
+        changes
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.advertised.contains_key(name)
+    }
+
+    pub fn list(&self) -> Vec<CapabilityInfo> {
+        let mut list: Vec<CapabilityInfo> = self
+            .advertised
+            .iter()
+            .map(|(name, metadata)| CapabilityInfo { name: name.clone(), metadata: metadata.clone() })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+}
@@ -0,0 +1,208 @@
+use crate::provenance::DocRecord;
+use crate::sync::DocManager;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Stream protocol used to catch up on documents directly with a peer.
+pub const PROTOCOL: &str = "/meshclaw/doc-sync/1.0.0";
+
+/// Largest slice of an update sent in one response.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DocState {
+    pub doc_id: String,
+    pub state_vector: Vec<u8>,
+    /// Encoded yrs snapshot, so deletions are compared as well as clocks.
+    #[serde(default)]
+    pub snapshot: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DocSyncRequest {
+    /// Sent on connection: the state vector of every document we hold, and
+    /// the creation records and tombstones we know of.
+    Hello {
+        docs: Vec<DocState>,
+        #[serde(default)]
+        records: Vec<DocRecord>,
+    },
+    /// Asks for the next chunk of the changes to `doc_id` that a node with
+    /// `state_vector` is missing.
+    Pull { doc_id: String, state_vector: Vec<u8>, offset: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DocSyncResponse {
+    Hello {
+        docs: Vec<DocState>,
+        #[serde(default)]
+        records: Vec<DocRecord>,
+    },
+    /// `data` is the slice of the update starting at `offset`; the whole update is `total` bytes.
+    Chunk { doc_id: String, offset: u64, total: u64, data: Vec<u8> },
+    Error { doc_id: String, message: String },
+}
+
+/// Both sides of the doc sync protocol. Each peer says hello with its state
+/// vectors, and the other pulls the updates it lacks chunk by chunk. An update
+/// is computed once per transfer and served from memory until its last chunk,
+/// so concurrent edits cannot shift the offsets.
+pub struct DocSync {
+    docs: Arc<DocManager>,
+    /// Updates being sent, by peer and document.
+    outgoing: HashMap<(String, String), Vec<u8>>,
+    /// Updates being received, with the state vector they were requested for
+    /// and whether the current chunk was already asked for again.
+    incoming: HashMap<(String, String), (Vec<u8>, Vec<u8>, bool)>,
+}
+
+impl DocSync {
+    pub fn new(docs: Arc<DocManager>) -> Self {
+        DocSync { docs, outgoing: HashMap::new(), incoming: HashMap::new() }
+    }
+
+    pub fn hello(&self) -> DocSyncRequest {
+        DocSyncRequest::Hello { docs: self.states(), records: self.docs.records() }
+    }
+
+    /// Applies the creations and deletions a peer knows of, so a node that
+    /// missed one catches up. Records we already have or that their signer
+    /// may not make are skipped.
+    fn apply_records(&self, peer: &str, records: Vec<DocRecord>) {
+        for record in records {
+            match self.docs.apply_record(&record) {
+                Ok(true) if record.deleted => println!("Doc sync: deleted '{}' as learned from {}", record.doc_id, peer),
+                Ok(true) => println!("Doc sync: created '{}' as learned from {}", record.doc_id, peer),
+                Ok(false) => {}
+                Err(e) => eprintln!("Doc sync: ignoring record for '{}' from {}: {}", record.doc_id, peer, e),
+            }
+        }
+    }
+
+    /// Asks `peer` for the changes to `doc_id` we are missing, unless a
+    /// transfer of it from that peer is already under way.
+    pub fn pull(&mut self, peer: &str, doc_id: &str) -> Option<DocSyncRequest> {
+        let key = (peer.to_string(), doc_id.to_string());
+        if self.incoming.contains_key(&key) {
+            return None;
+        }
+        let state_vector = self.docs.get(doc_id)?.state_vector();
+        self.incoming.insert(key, (state_vector.clone(), Vec::new(), false));
+        Some(DocSyncRequest::Pull { doc_id: doc_id.to_string(), state_vector, offset: 0 })
+    }
+
+    fn states(&self) -> Vec<DocState> {
+        self.docs
+            .list()
+            .into_iter()
+            .filter_map(|doc_id| {
+                let doc = self.docs.get(&doc_id)?;
+                Some(DocState { doc_id, state_vector: doc.state_vector(), snapshot: doc.snapshot_state() })
+            })
+            .collect()
+    }
+
+    /// Answers a request from `peer`.
+    pub fn answer(&mut self, peer: &str, request: DocSyncRequest) -> DocSyncResponse {
+        let (doc_id, state_vector, offset) = match request {
+            DocSyncRequest::Hello { records, .. } => {
+                self.apply_records(peer, records);
+                return DocSyncResponse::Hello { docs: self.states(), records: self.docs.records() };
+            },
+            DocSyncRequest::Pull { doc_id, state_vector, offset } => (doc_id, state_vector, offset),
+        };
+        let key = (peer.to_string(), doc_id.clone());
+        if offset == 0 || !self.outgoing.contains_key(&key) {
+            let update = match self.docs.get(&doc_id).map(|doc| doc.diff(&state_vector)) {
+                Some(Ok(update)) => update,
+                Some(Err(e)) => return DocSyncResponse::Error { doc_id, message: e.to_string() },
+                None => return DocSyncResponse::Error { doc_id, message: "unknown document".to_string() },
+            };
+            self.outgoing.insert(key.clone(), update);
+        }
+
+        let update = &self.outgoing[&key];
+        let total = update.len() as u64;
+        let start = (offset as usize).min(update.len());
+        let end = (start + CHUNK_SIZE).min(update.len());
+        let data = update[start..end].to_vec();
+        if end == update.len() {
+            self.outgoing.remove(&key);
+        }
+        DocSyncResponse::Chunk { doc_id, offset: start as u64, total, data }
+    }
+
+    /// Handles a response from `peer`, returning the requests to send next.
+    pub fn receive(&mut self, peer: &str, response: DocSyncResponse) -> Vec<DocSyncRequest> {
+        match response {
+            DocSyncResponse::Hello { docs, records } => {
+                self.apply_records(peer, records);
+                docs.into_iter()
+                    .filter(|theirs| match self.docs.get(&theirs.doc_id) {
+                        Some(doc) => doc.is_behind(&theirs.snapshot),
+                        None => true,
+                    })
+                    .filter_map(|theirs| {
+                        let state_vector = self.docs.open_doc(&theirs.doc_id).ok()?.state_vector();
+                        let key = (peer.to_string(), theirs.doc_id.clone());
+                        self.incoming.insert(key, (state_vector.clone(), Vec::new(), false));
+                        Some(DocSyncRequest::Pull { doc_id: theirs.doc_id, state_vector, offset: 0 })
+                    })
+                    .collect()
+            },
+            DocSyncResponse::Chunk { doc_id, offset, total, data } => {
+                let key = (peer.to_string(), doc_id.clone());
+                let (state_vector, buffer, retried) = match self.incoming.get_mut(&key) {
+                    Some(entry) if entry.1.len() as u64 == offset => entry,
+                    _ => {
+                        eprintln!("Doc sync: unexpected chunk of '{}' from {}", doc_id, peer);
+                        self.incoming.remove(&key);
+                        return Vec::new();
+                    }
+                };
+                buffer.extend_from_slice(&data);
+                let received = buffer.len() as u64;
+                if received < total {
+                    // An empty chunk short of the end is asked for once more
+                    // before the transfer is given up.
+                    if data.is_empty() && *retried {
+                        eprintln!("Doc sync: {} sent no data for '{}' at {} of {} bytes", peer, doc_id, received, total);
+                        self.incoming.remove(&key);
+                        return Vec::new();
+                    }
+                    *retried = data.is_empty();
+                    return vec![DocSyncRequest::Pull { doc_id, state_vector: state_vector.clone(), offset: received }];
+                }
+
+                let (_, update, _) = self.incoming.remove(&key).unwrap_or_default();
+                if received > total {
+                    eprintln!("Doc sync: '{}' from {} overran its {} bytes", doc_id, peer, total);
+                    return Vec::new();
+                }
+                if update.is_empty() {
+                    return Vec::new();
+                }
+                match self.docs.get(&doc_id).map(|doc| doc.apply_remote(&update, peer, false)) {
+                    Some(Ok(_)) => println!("Doc sync: caught up on '{}' from {} ({} bytes)", doc_id, peer, update.len()),
+                    Some(Err(e)) => eprintln!("Doc sync: failed to apply '{}' from {}: {}", doc_id, peer, e),
+                    None => {}
+                }
+                Vec::new()
+            }
+            DocSyncResponse::Error { doc_id, message } => {
+                eprintln!("Doc sync: {} could not send '{}': {}", peer, doc_id, message);
+                self.incoming.remove(&(peer.to_string(), doc_id));
+                Vec::new()
+            }
+        }
+    }
+
+    /// Drops transfers with a peer whose connection closed.
+    pub fn forget(&mut self, peer: &str) {
+        self.outgoing.retain(|(p, _), _| p != peer);
+        self.incoming.retain(|(p, _), _| p != peer);
+    }
+}
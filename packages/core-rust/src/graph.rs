@@ -0,0 +1,265 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+/// One node of a task graph. `inputs` binds names to upstream outputs, either
+/// `"<step>"` for the whole output or `"<step>.<field>..."` for part of a
+/// structured one; bound values replace `{{name}}` in `task_desc` and `payload`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphStep {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capability: Option<String>,
+    #[serde(default)]
+    pub task_desc: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    pub inputs: HashMap<String, String>,
+}
+
+impl GraphStep {
+    /// Explicit dependencies plus every step an input is bound to.
+    fn dependencies(&self) -> HashSet<String> {
+        let mut deps: HashSet<String> = self.depends_on.iter().cloned().collect();
+        for source in self.inputs.values() {
+            deps.insert(source.split('.').next().unwrap_or_default().to_string());
+        }
+        deps
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum GraphError {
+    Empty,
+    DuplicateStep(String),
+    UnknownDependency { step: String, dependency: String },
+    Cycle,
+}
+
+impl GraphError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GraphError::Empty => "empty-graph",
+            GraphError::DuplicateStep(_) => "duplicate-step",
+            GraphError::UnknownDependency { .. } => "unknown-dependency",
+            GraphError::Cycle => "cycle",
+        }
+    }
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Empty => write!(f, "task graph has no steps"),
+            GraphError::DuplicateStep(id) => write!(f, "step '{}' is defined more than once", id),
+            GraphError::UnknownDependency { step, dependency } => {
+                write!(f, "step '{}' depends on unknown step '{}'", step, dependency)
+            }
+            GraphError::Cycle => write!(f, "task graph contains a cycle"),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+#[derive(Debug, Clone)]
+pub enum StepState {
+    Pending,
+    Running,
+    Completed(serde_json::Value),
+    Failed(serde_json::Value),
+    /// Not run because an upstream step failed.
+    Skipped,
+}
+
+/// A step whose dependencies have completed, with its inputs bound.
+#[derive(Debug, Clone)]
+pub struct ReadyStep {
+    pub id: String,
+    pub capability: Option<String>,
+    pub task_desc: String,
+    pub payload: serde_json::Value,
+}
+
+/// Execution state of a task graph.
+pub struct GraphRun {
+    steps: Vec<GraphStep>,
+    deps: HashMap<String, HashSet<String>>,
+    states: HashMap<String, StepState>,
+}
+
+impl GraphRun {
+    /// Validates that step IDs are unique, dependencies exist and the graph is acyclic.
+    pub fn new(steps: Vec<GraphStep>) -> Result<Self, GraphError> {
+        if steps.is_empty() {
+            return Err(GraphError::Empty);
+        }
+        let mut deps = HashMap::new();
+        for step in &steps {
+            if deps.insert(step.id.clone(), step.dependencies()).is_some() {
+                return Err(GraphError::DuplicateStep(step.id.clone()));
+            }
+        }
+        for (step, step_deps) in &deps {
+            if let Some(dependency) = step_deps.iter().find(|d| !deps.contains_key(*d)) {
+                return Err(GraphError::UnknownDependency { step: step.clone(), dependency: dependency.clone() });
+            }
+        }
+
+        // Kahn's algorithm: every step must eventually have no unmet dependencies.
+        let mut remaining: HashMap<&str, usize> = deps.iter().map(|(id, d)| (id.as_str(), d.len())).collect();
+        let mut queue: VecDeque<&str> = remaining.iter().filter(|(_, n)| **n == 0).map(|(id, _)| *id).collect();
+        let mut visited = 0;
+        while let Some(id) = queue.pop_front() {
+            visited += 1;
+            for (other, other_deps) in &deps {
+                if other_deps.contains(id) {
+                    let n = remaining.get_mut(other.as_str()).unwrap();
+                    *n -= 1;
+                    if *n == 0 {
+                        queue.push_back(other.as_str());
+                    }
+                }
+            }
+        }
+        if visited != deps.len() {
+            return Err(GraphError::Cycle);
+        }
+
+        let states = steps.iter().map(|s| (s.id.clone(), StepState::Pending)).collect();
+        Ok(GraphRun { steps, deps, states })
+    }
+
+    /// Marks every pending step whose dependencies completed as running and
+    /// returns it with its inputs bound.
+    pub fn take_ready(&mut self) -> Vec<ReadyStep> {
+        let mut ready = Vec::new();
+        for step in &self.steps {
+            if !matches!(self.states[&step.id], StepState::Pending) {
+                continue;
+            }
+            let deps_done = self.deps[&step.id]
+                .iter()
+                .all(|d| matches!(self.states[d], StepState::Completed(_)));
+            if !deps_done {
+                continue;
+            }
+
+            let mut bindings = HashMap::new();
+            for (name, source) in &step.inputs {
+                bindings.insert(name.as_str(), self.resolve(source));
+            }
+            ready.push(ReadyStep {
+                id: step.id.clone(),
+                capability: step.capability.clone(),
+                task_desc: substitute(&step.task_desc, &bindings),
+                payload: bind(&step.payload, &bindings),
+            });
+        }
+        for step in &ready {
+            self.states.insert(step.id.clone(), StepState::Running);
+        }
+        ready
+    }
+
+    pub fn complete(&mut self, step_id: &str, output: serde_json::Value) {
+        self.states.insert(step_id.to_string(), StepState::Completed(output));
+    }
+
+    /// Records a failure and skips everything downstream of the step.
+    pub fn fail(&mut self, step_id: &str, error: serde_json::Value) {
+        self.states.insert(step_id.to_string(), StepState::Failed(error));
+        let mut blocked = vec![step_id.to_string()];
+        while let Some(id) = blocked.pop() {
+            for (other, other_deps) in &self.deps {
+                if other_deps.contains(&id) && matches!(self.states[other], StepState::Pending) {
+                    self.states.insert(other.clone(), StepState::Skipped);
+                    blocked.push(other.clone());
+                }
+            }
+        }
+    }
+
+    /// True once no step is pending or running.
+    pub fn is_finished(&self) -> bool {
+        self.states.values().all(|s| !matches!(s, StepState::Pending | StepState::Running))
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.states.values().all(|s| matches!(s, StepState::Completed(_)))
+    }
+
+    /// Per-step status with outputs of completed steps and errors of failed ones.
+    pub fn report(&self) -> serde_json::Value {
+        let mut steps = serde_json::Map::new();
+        for step in &self.steps {
+            let entry = match &self.states[&step.id] {
+                StepState::Pending => serde_json::json!({ "status": "pending" }),
+                StepState::Running => serde_json::json!({ "status": "running" }),
+                StepState::Completed(output) => serde_json::json!({ "status": "completed", "output": output }),
+                StepState::Failed(error) => serde_json::json!({ "status": "failed", "error": error }),
+                StepState::Skipped => serde_json::json!({ "status": "skipped" }),
+            };
+            steps.insert(step.id.clone(), entry);
+        }
+        serde_json::json!({ "steps": steps })
+    }
+
+    fn resolve(&self, source: &str) -> serde_json::Value {
+        let mut parts = source.split('.');
+        let step = parts.next().unwrap_or_default();
+        let mut value = match self.states.get(step) {
+            Some(StepState::Completed(output)) => output,
+            _ => return serde_json::Value::Null,
+        };
+        for part in parts {
+            let next = match part.parse::<usize>() {
+                Ok(i) if value.is_array() => value.get(i),
+                _ => value.get(part),
+            };
+            value = match next {
+                Some(v) => v,
+                None => return serde_json::Value::Null,
+            };
+        }
+        value.clone()
+    }
+}
+
+fn as_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn substitute(template: &str, bindings: &HashMap<&str, serde_json::Value>) -> String {
+    let mut out = template.to_string();
+    for (name, value) in bindings {
+        out = out.replace(&format!("{{{{{}}}}}", name), &as_text(value));
+    }
+    out
+}
+
+/// Replaces placeholders inside payload strings. A string that is exactly one
+/// placeholder takes the bound value as-is, keeping its JSON type.
+fn bind(value: &serde_json::Value, bindings: &HashMap<&str, serde_json::Value>) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => {
+            let name = s.strip_prefix("{{").and_then(|s| s.strip_suffix("}}"));
+            match name.and_then(|n| bindings.get(n)) {
+                Some(bound) => bound.clone(),
+                None => serde_json::Value::String(substitute(s, bindings)),
+            }
+        }
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.iter().map(|v| bind(v, bindings)).collect()),
+        serde_json::Value::Object(map) => {
+            serde_json::Value::Object(map.iter().map(|(k, v)| (k.clone(), bind(v, bindings))).collect())
+        }
+        other => other.clone(),
+    }
+}
@@ -0,0 +1,819 @@
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const DEFAULT_CAPABILITY: &str = "llm:llama3";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InferenceRequest {
+    pub model: String,
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub options: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    /// Ask the backend to constrain output to a JSON document.
+    #[serde(default)]
+    pub json: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Generation options read from the `payload` of an LLM `Delegate`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationParams {
+    /// Runs the task on `llm:<model>` instead of the requested capability.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// Shared memory keys whose text is prepended to the prompt.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context: Vec<String>,
+}
+
+impl GenerationParams {
+    pub fn schema() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "model": { "type": "string" },
+                "system": { "type": "string" },
+                "temperature": { "type": "number" },
+                "maxTokens": { "type": "integer" },
+                "format": { "type": "string", "enum": ["text", "json"] },
+                "context": { "type": "array", "items": { "type": "string" } },
+                "scheduling": { "type": "object" },
+                "redundancy": { "type": "object" }
+            },
+            "additionalProperties": false
+        })
+    }
+
+    /// Validates a `Delegate` payload; `null` means all defaults.
+    pub fn from_payload(payload: &serde_json::Value) -> Result<Self, InferenceError> {
+        if payload.is_null() {
+            return Ok(GenerationParams::default());
+        }
+        crate::tools::validate(&Self::schema(), payload, "payload").map_err(InferenceError::InvalidRequest)?;
+        let params: GenerationParams =
+            serde_json::from_value(payload.clone()).map_err(|e| InferenceError::InvalidRequest(e.to_string()))?;
+        if let Some(t) = params.temperature {
+            if !(0.0..=2.0).contains(&t) {
+                return Err(InferenceError::InvalidRequest("payload.temperature must be between 0 and 2".to_string()));
+            }
+        }
+        if params.max_tokens == Some(0) {
+            return Err(InferenceError::InvalidRequest("payload.maxTokens must be positive".to_string()));
+        }
+        if params.model.as_deref().is_some_and(|m| m.is_empty()) {
+            return Err(InferenceError::InvalidRequest("payload.model must not be empty".to_string()));
+        }
+        Ok(params)
+    }
+
+    /// Prepends the text of each `context` key to the prompt.
+    pub fn expand_prompt(&self, prompt: &str, memory: &crate::sync::MemorySync) -> Result<String, InferenceError> {
+        let mut full = String::new();
+        for key in &self.context {
+            let text = memory
+                .get_text(key)
+                .ok_or_else(|| InferenceError::InvalidRequest(format!("unknown context key '{}'", key)))?;
+            full.push_str(&format!("[{}]\n{}\n\n", key, text));
+        }
+        full.push_str(prompt);
+        Ok(full)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InferenceResponse {
+    pub model: String,
+    pub text: String,
+    /// Token counts, when the backend reports them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// Ollama reports `prompt_eval_count` and `eval_count` on its final response.
+    fn from_ollama(json: &serde_json::Value) -> Option<Self> {
+        let completion_tokens = json["eval_count"].as_u64()?;
+        Some(TokenUsage { prompt_tokens: json["prompt_eval_count"].as_u64().unwrap_or(0), completion_tokens })
+    }
+
+    /// OpenAI-compatible servers report a `usage` object.
+    fn from_openai(json: &serde_json::Value) -> Option<Self> {
+        let usage = json.get("usage").filter(|u| u.is_object())?;
+        Some(TokenUsage {
+            prompt_tokens: usage["prompt_tokens"].as_u64().unwrap_or(0),
+            completion_tokens: usage["completion_tokens"].as_u64().unwrap_or(0),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelInfo {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameter_size: Option<String>,
+}
+
+impl ModelInfo {
+    pub fn named(name: &str) -> Self {
+        ModelInfo { name: name.to_string(), size: None, context_length: None, parameter_size: None }
+    }
+
+    /// Whether this installed model satisfies a configured model name;
+    /// Ollama reports untagged models as `<name>:latest`.
+    pub fn matches(&self, model: &str) -> bool {
+        self.name == model || self.name.strip_suffix(":latest") == Some(model)
+    }
+
+    /// Capability advertised for this model, e.g. `llm:llama3` for `llama3:latest`.
+    pub fn capability(&self) -> String {
+        format!("llm:{}", self.name.strip_suffix(":latest").unwrap_or(&self.name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum InferenceError {
+    UnknownCapability(String),
+    InvalidRequest(String),
+    Unavailable(String),
+    Rejected { status: u16, body: String },
+    InvalidResponse(String),
+}
+
+impl InferenceError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InferenceError::UnknownCapability(_) => "unknown-capability",
+            InferenceError::InvalidRequest(_) => "invalid-request",
+            InferenceError::Unavailable(_) => "unavailable",
+            InferenceError::Rejected { .. } => "rejected",
+            InferenceError::InvalidResponse(_) => "invalid-response",
+        }
+    }
+}
+
+impl fmt::Display for InferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InferenceError::UnknownCapability(cap) => write!(f, "no inference backend configured for '{}'", cap),
+            InferenceError::InvalidRequest(e) => write!(f, "invalid request: {}", e),
+            InferenceError::Unavailable(e) => write!(f, "inference backend unreachable: {}", e),
+            InferenceError::Rejected { status, body } => write!(f, "inference backend returned HTTP {}: {}", status, body),
+            InferenceError::InvalidResponse(e) => write!(f, "invalid inference response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for InferenceError {}
+
+pub trait InferenceBackend: Send + Sync {
+    fn name(&self) -> &str;
+    fn list_models<'a>(&'a self) -> BoxFuture<'a, Result<Vec<ModelInfo>, InferenceError>>;
+    fn generate<'a>(&'a self, req: InferenceRequest) -> BoxFuture<'a, Result<InferenceResponse, InferenceError>>;
+
+    /// Sends partial tokens to `tokens` as they are produced and returns the
+    /// full completion. Backends without streaming support emit one chunk.
+    fn generate_stream<'a>(
+        &'a self,
+        req: InferenceRequest,
+        tokens: UnboundedSender<String>,
+    ) -> BoxFuture<'a, Result<InferenceResponse, InferenceError>> {
+        Box::pin(async move {
+            let resp = self.generate(req).await?;
+            let _ = tokens.send(resp.text.clone());
+            Ok(resp)
+        })
+    }
+}
+
+async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: Option<&str>,
+    body: &serde_json::Value,
+) -> Result<reqwest::Response, InferenceError> {
+    let mut builder = client.post(url).json(body);
+    if let Some(key) = api_key {
+        builder = builder.bearer_auth(key);
+    }
+    let resp = builder.send().await.map_err(|e| InferenceError::Unavailable(e.to_string()))?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        return Err(InferenceError::Rejected { status: status.as_u16(), body });
+    }
+    Ok(resp)
+}
+
+async fn get_json(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: Option<&str>,
+) -> Result<serde_json::Value, InferenceError> {
+    let mut builder = client.get(url);
+    if let Some(key) = api_key {
+        builder = builder.bearer_auth(key);
+    }
+    let resp = builder.send().await.map_err(|e| InferenceError::Unavailable(e.to_string()))?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        return Err(InferenceError::Rejected { status: status.as_u16(), body });
+    }
+    resp.json::<serde_json::Value>()
+        .await
+        .map_err(|e| InferenceError::InvalidResponse(e.to_string()))
+}
+
+async fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: Option<&str>,
+    body: &serde_json::Value,
+) -> Result<serde_json::Value, InferenceError> {
+    send(client, url, api_key, body)
+        .await?
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| InferenceError::InvalidResponse(e.to_string()))
+}
+
+/// Feeds each non-empty line of a streamed body to `on_line` until it returns
+/// `Ok(true)`, marking the end of the response. A body that ends before then
+/// was cut off, so the backend is reported unavailable.
+async fn read_lines<F>(mut resp: reqwest::Response, mut on_line: F) -> Result<(), InferenceError>
+where
+    F: FnMut(&str) -> Result<bool, InferenceError>,
+{
+    let mut buf: Vec<u8> = Vec::new();
+    while let Some(bytes) = resp.chunk().await.map_err(|e| InferenceError::Unavailable(e.to_string()))? {
+        buf.extend_from_slice(&bytes);
+        while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if !line.is_empty() && on_line(line)? {
+                return Ok(());
+            }
+        }
+    }
+    let rest = String::from_utf8_lossy(&buf);
+    let rest = rest.trim();
+    if !rest.is_empty() && on_line(rest)? {
+        return Ok(());
+    }
+    Err(InferenceError::Unavailable("stream ended before the response was complete".to_string()))
+}
+
+/// Ollama's native `/api/generate` endpoint.
+pub struct OllamaBackend {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl OllamaBackend {
+    pub fn new(base_url: &str) -> Self {
+        OllamaBackend {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn request_body(&self, req: &InferenceRequest, stream: bool) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "model": req.model,
+            "prompt": req.prompt,
+            "stream": stream
+        });
+        if let Some(system) = &req.system {
+            body["system"] = serde_json::Value::String(system.clone());
+        }
+        if req.options.is_object() {
+            body["options"] = req.options.clone();
+        }
+        if let Some(temperature) = req.temperature {
+            body["options"]["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(max_tokens) = req.max_tokens {
+            body["options"]["num_predict"] = serde_json::json!(max_tokens);
+        }
+        if req.json {
+            body["format"] = serde_json::Value::String("json".to_string());
+        }
+        body
+    }
+}
+
+impl InferenceBackend for OllamaBackend {
+    fn name(&self) -> &str {
+        "ollama"
+    }
+
+    fn list_models<'a>(&'a self) -> BoxFuture<'a, Result<Vec<ModelInfo>, InferenceError>> {
+        Box::pin(async move {
+            let tags = get_json(&self.client, &format!("{}/api/tags", self.base_url), None).await?;
+            let mut models = Vec::new();
+            for entry in tags["models"].as_array().into_iter().flatten() {
+                let name = match entry["name"].as_str() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let mut info = ModelInfo::named(name);
+                info.size = entry["size"].as_u64();
+                info.parameter_size = entry["details"]["parameter_size"].as_str().map(|s| s.to_string());
+
+                // Context length is only reported per model, under an architecture-specific key.
+                let show = serde_json::json!({ "model": name });
+                if let Ok(details) = post_json(&self.client, &format!("{}/api/show", self.base_url), None, &show).await {
+                    info.context_length = details["model_info"]
+                        .as_object()
+                        .and_then(|m| m.iter().find(|(k, _)| k.ends_with(".context_length")))
+                        .and_then(|(_, v)| v.as_u64());
+                }
+                models.push(info);
+            }
+            Ok(models)
+        })
+    }
+
+    fn generate<'a>(&'a self, req: InferenceRequest) -> BoxFuture<'a, Result<InferenceResponse, InferenceError>> {
+        Box::pin(async move {
+            let body = self.request_body(&req, false);
+            let url = format!("{}/api/generate", self.base_url);
+            let json = post_json(&self.client, &url, None, &body).await?;
+            let text = json["response"]
+                .as_str()
+                .ok_or_else(|| InferenceError::InvalidResponse("missing 'response' field".to_string()))?;
+            Ok(InferenceResponse { model: req.model, text: text.to_string(), usage: TokenUsage::from_ollama(&json) })
+        })
+    }
+
+    fn generate_stream<'a>(
+        &'a self,
+        req: InferenceRequest,
+        tokens: UnboundedSender<String>,
+    ) -> BoxFuture<'a, Result<InferenceResponse, InferenceError>> {
+        Box::pin(async move {
+            let body = self.request_body(&req, true);
+            let url = format!("{}/api/generate", self.base_url);
+            let resp = send(&self.client, &url, None, &body).await?;
+
+            // Ollama streams newline-delimited JSON objects, the last one with `done: true`.
+            let mut text = String::new();
+            let mut usage = None;
+            read_lines(resp, |line| {
+                let json: serde_json::Value = serde_json::from_str(line)
+                    .map_err(|e| InferenceError::InvalidResponse(e.to_string()))?;
+                if let Some(err) = json["error"].as_str() {
+                    return Err(InferenceError::InvalidResponse(err.to_string()));
+                }
+                if let Some(token) = json["response"].as_str().filter(|t| !t.is_empty()) {
+                    text.push_str(token);
+                    let _ = tokens.send(token.to_string());
+                }
+                let done = json["done"].as_bool().unwrap_or(false);
+                if done {
+                    usage = TokenUsage::from_ollama(&json);
+                }
+                Ok(done)
+            })
+            .await?;
+            Ok(InferenceResponse { model: req.model, text, usage })
+        })
+    }
+}
+
+/// OpenAI-compatible `/v1/chat/completions` servers (llama.cpp server, vLLM, ...).
+pub struct OpenAiCompatBackend {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl OpenAiCompatBackend {
+    pub fn new(base_url: &str, api_key: Option<String>) -> Self {
+        OpenAiCompatBackend {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+
+    fn request_body(&self, req: &InferenceRequest, stream: bool) -> serde_json::Value {
+        let mut messages = Vec::new();
+        if let Some(system) = &req.system {
+            messages.push(serde_json::json!({ "role": "system", "content": system }));
+        }
+        messages.push(serde_json::json!({ "role": "user", "content": req.prompt }));
+
+        let mut body = serde_json::json!({
+            "model": req.model,
+            "messages": messages,
+            "stream": stream
+        });
+        if stream {
+            // Token counts are only sent in a final chunk when asked for.
+            body["stream_options"] = serde_json::json!({ "include_usage": true });
+        }
+        if let Some(options) = req.options.as_object() {
+            for (k, v) in options {
+                body[k] = v.clone();
+            }
+        }
+        if let Some(temperature) = req.temperature {
+            body["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(max_tokens) = req.max_tokens {
+            body["max_tokens"] = serde_json::json!(max_tokens);
+        }
+        if req.json {
+            body["response_format"] = serde_json::json!({ "type": "json_object" });
+        }
+        body
+    }
+}
+
+impl InferenceBackend for OpenAiCompatBackend {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn list_models<'a>(&'a self) -> BoxFuture<'a, Result<Vec<ModelInfo>, InferenceError>> {
+        Box::pin(async move {
+            let url = format!("{}/v1/models", self.base_url);
+            let json = get_json(&self.client, &url, self.api_key.as_deref()).await?;
+            Ok(json["data"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|m| m["id"].as_str())
+                .map(ModelInfo::named)
+                .collect())
+        })
+    }
+
+    fn generate<'a>(&'a self, req: InferenceRequest) -> BoxFuture<'a, Result<InferenceResponse, InferenceError>> {
+        Box::pin(async move {
+            let body = self.request_body(&req, false);
+            let url = format!("{}/v1/chat/completions", self.base_url);
+            let json = post_json(&self.client, &url, self.api_key.as_deref(), &body).await?;
+            let text = json["choices"][0]["message"]["content"]
+                .as_str()
+                .ok_or_else(|| InferenceError::InvalidResponse("missing 'choices[0].message.content'".to_string()))?;
+            Ok(InferenceResponse { model: req.model, text: text.to_string(), usage: TokenUsage::from_openai(&json) })
+        })
+    }
+
+    fn generate_stream<'a>(
+        &'a self,
+        req: InferenceRequest,
+        tokens: UnboundedSender<String>,
+    ) -> BoxFuture<'a, Result<InferenceResponse, InferenceError>> {
+        Box::pin(async move {
+            let body = self.request_body(&req, true);
+            let url = format!("{}/v1/chat/completions", self.base_url);
+            let resp = send(&self.client, &url, self.api_key.as_deref(), &body).await?;
+
+            // Server-sent events: `data: {...}` lines terminated by `data: [DONE]`.
+            let mut text = String::new();
+            let mut usage = None;
+            read_lines(resp, |line| {
+                let data = match line.strip_prefix("data:") {
+                    Some(data) => data.trim(),
+                    None => return Ok(false),
+                };
+                if data == "[DONE]" {
+                    return Ok(true);
+                }
+                let json: serde_json::Value = serde_json::from_str(data)
+                    .map_err(|e| InferenceError::InvalidResponse(e.to_string()))?;
+                if let Some(token) = json["choices"][0]["delta"]["content"].as_str().filter(|t| !t.is_empty()) {
+                    text.push_str(token);
+                    let _ = tokens.send(token.to_string());
+                }
+                if let Some(reported) = TokenUsage::from_openai(&json) {
+                    usage = Some(reported);
+                }
+                Ok(false)
+            })
+            .await?;
+            Ok(InferenceResponse { model: req.model, text, usage })
+        })
+    }
+}
+
+/// Deterministic backend for tests and offline demos: echoes the prompt, or
+/// returns a fixed reply when one is configured.
+pub struct MockBackend {
+    reply: Option<String>,
+    models: Vec<String>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend { reply: None, models: vec!["mock".to_string()] }
+    }
+
+    pub fn with_reply(reply: &str) -> Self {
+        MockBackend { reply: Some(reply.to_string()), ..Self::new() }
+    }
+
+    pub fn with_models(models: Vec<String>) -> Self {
+        MockBackend { reply: None, models }
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InferenceBackend for MockBackend {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn list_models<'a>(&'a self) -> BoxFuture<'a, Result<Vec<ModelInfo>, InferenceError>> {
+        Box::pin(async move { Ok(self.models.iter().map(|m| ModelInfo::named(m)).collect()) })
+    }
+
+    fn generate<'a>(&'a self, req: InferenceRequest) -> BoxFuture<'a, Result<InferenceResponse, InferenceError>> {
+        Box::pin(async move {
+            let text = match &self.reply {
+                Some(reply) => reply.clone(),
+                None => format!("[mock:{}] {}", req.model, req.prompt),
+            };
+            Ok(InferenceResponse { model: req.model, text, usage: None })
+        })
+    }
+
+    fn generate_stream<'a>(
+        &'a self,
+        req: InferenceRequest,
+        tokens: UnboundedSender<String>,
+    ) -> BoxFuture<'a, Result<InferenceResponse, InferenceError>> {
+        Box::pin(async move {
+            let resp = self.generate(req).await?;
+            for word in resp.text.split_inclusive(' ') {
+                let _ = tokens.send(word.to_string());
+            }
+            Ok(resp)
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Ollama,
+    Openai,
+    Mock,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RouteConfig {
+    pub backend: BackendKind,
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    pub model: String,
+    #[serde(default)]
+    pub options: serde_json::Value,
+    #[serde(default, rename = "apiKey")]
+    pub api_key: Option<String>,
+}
+
+impl RouteConfig {
+    fn build_backend(&self) -> Arc<dyn InferenceBackend> {
+        match self.backend {
+            BackendKind::Ollama => Arc::new(OllamaBackend::new(
+                self.endpoint.as_deref().unwrap_or("http://localhost:11434"),
+            )),
+            BackendKind::Openai => Arc::new(OpenAiCompatBackend::new(
+                self.endpoint.as_deref().unwrap_or("http://localhost:8080"),
+                self.api_key.clone(),
+            )),
+            BackendKind::Mock => Arc::new(MockBackend::with_models(vec![self.model.clone()])),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Route {
+    pub backend: Arc<dyn InferenceBackend>,
+    pub model: String,
+    pub options: serde_json::Value,
+}
+
+/// Maps capability names (e.g. `llm:llama3`) to a backend, model and options.
+/// Routes for models found while probing a backend are added at runtime.
+#[derive(Default)]
+pub struct InferenceRouter {
+    backends: Vec<Arc<dyn InferenceBackend>>,
+    routes: RwLock<HashMap<String, Route>>,
+}
+
+impl InferenceRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: HashMap<String, RouteConfig>) -> Self {
+        let mut router = InferenceRouter::new();
+        // Routes pointing at the same server share one backend so it is probed once.
+        let mut shared = HashMap::<(BackendKind, Option<String>), Arc<dyn InferenceBackend>>::new();
+        for (capability, cfg) in config {
+            let backend = if cfg.backend == BackendKind::Mock {
+                let backend = cfg.build_backend();
+                router.backends.push(backend.clone());
+                backend
+            } else {
+                shared
+                    .entry((cfg.backend, cfg.endpoint.clone()))
+                    .or_insert_with(|| {
+                        let backend = cfg.build_backend();
+                        router.backends.push(backend.clone());
+                        backend
+                    })
+                    .clone()
+            };
+            router.insert(&capability, Route {
+                backend,
+                model: cfg.model.clone(),
+                options: cfg.options.clone(),
+            });
+        }
+        router
+    }
+
+    /// Reads `MESHCLAW_INFERENCE_CONFIG` (default `./.meshclaw/inference.json`),
+    /// falling back to a local Ollama serving `llama3`.
+    pub fn load() -> Self {
+        let path_str = std::env::var("MESHCLAW_INFERENCE_CONFIG")
+            .unwrap_or_else(|_| "./.meshclaw/inference.json".to_string());
+        let path = Path::new(&path_str);
+        if path.exists() {
+            match std::fs::read(path).map_err(anyhow::Error::from).and_then(|bytes| {
+                serde_json::from_slice::<HashMap<String, RouteConfig>>(&bytes).map_err(anyhow::Error::from)
+            }) {
+                Ok(config) => return InferenceRouter::from_config(config),
+                Err(e) => eprintln!("Failed to load inference config {}: {}", path_str, e),
+            }
+        }
+
+        let mut router = InferenceRouter::new();
+        let backend: Arc<dyn InferenceBackend> = Arc::new(OllamaBackend::new("http://localhost:11434"));
+        router.backends.push(backend.clone());
+        router.insert(DEFAULT_CAPABILITY, Route {
+            backend,
+            model: "llama3".to_string(),
+            options: serde_json::Value::Null,
+        });
+        router
+    }
+
+    pub fn insert(&self, capability: &str, route: Route) {
+        self.routes.write().unwrap().insert(capability.to_string(), route);
+    }
+
+    pub fn route(&self, capability: &str) -> Option<Route> {
+        self.routes.read().unwrap().get(capability).cloned()
+    }
+
+    /// The route a task for `capability` runs on, honouring a `model` override.
+    pub fn resolve(&self, capability: &str, params: &GenerationParams) -> Result<Route, InferenceError> {
+        let capability = match &params.model {
+            Some(model) => format!("llm:{}", model),
+            None => capability.to_string(),
+        };
+        self.route(&capability).ok_or(InferenceError::UnknownCapability(capability))
+    }
+
+    pub fn capabilities(&self) -> Vec<String> {
+        self.routes.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Asks every backend for its installed models and returns the capabilities
+    /// that can actually be served, with the model details as metadata.
+    /// Installed models without a configured route get an `llm:<model>` route.
+    pub async fn probe(&self) -> Vec<(String, ModelInfo)> {
+        let mut available = Vec::new();
+        for backend in &self.backends {
+            let models = match backend.list_models().await {
+                Ok(models) => models,
+                Err(e) => {
+                    eprintln!("Inference backend '{}' unavailable: {}", backend.name(), e);
+                    continue;
+                }
+            };
+
+            let routes: Vec<(String, Route)> = self
+                .routes
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|(_, r)| Arc::ptr_eq(&r.backend, backend))
+                .map(|(c, r)| (c.clone(), r.clone()))
+                .collect();
+
+            for model in models {
+                let mut routed = false;
+                for (capability, route) in &routes {
+                    if model.matches(&route.model) {
+                        available.push((capability.clone(), model.clone()));
+                        routed = true;
+                    }
+                }
+                if !routed {
+                    let capability = model.capability();
+                    if self.route(&capability).is_none() {
+                        self.insert(&capability, Route {
+                            backend: backend.clone(),
+                            model: model.name.clone(),
+                            options: serde_json::Value::Null,
+                        });
+                        available.push((capability, model));
+                    }
+                }
+            }
+        }
+        available
+    }
+
+    fn request(
+        &self,
+        capability: &str,
+        prompt: &str,
+        params: &GenerationParams,
+    ) -> Result<(Route, InferenceRequest), InferenceError> {
+        let route = self.resolve(capability, params)?;
+        let req = InferenceRequest {
+            model: route.model.clone(),
+            prompt: prompt.to_string(),
+            system: params.system.clone(),
+            options: route.options.clone(),
+            temperature: params.temperature,
+            max_tokens: params.max_tokens,
+            json: params.format == OutputFormat::Json,
+        };
+        Ok((route, req))
+    }
+
+    pub async fn generate(&self, capability: &str, prompt: &str) -> Result<InferenceResponse, InferenceError> {
+        let (route, req) = self.request(capability, prompt, &GenerationParams::default())?;
+        route.backend.generate(req).await
+    }
+
+    pub async fn generate_stream(
+        &self,
+        capability: &str,
+        prompt: &str,
+        params: &GenerationParams,
+        tokens: UnboundedSender<String>,
+    ) -> Result<InferenceResponse, InferenceError> {
+        let (route, req) = self.request(capability, prompt, params)?;
+        route.backend.generate_stream(req, tokens).await
+    }
+}
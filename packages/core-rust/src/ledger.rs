@@ -0,0 +1,213 @@
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Verified receipts kept per peer; older ones are dropped.
+const MAX_RECEIPTS: usize = 20;
+
+/// A provider's signed statement that it ran a task for a requester.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Receipt {
+    pub task_id: String,
+    pub requester_id: String,
+    pub provider_id: String,
+    pub status: String,
+    pub duration_ms: u64,
+    pub timestamp: u64,
+    /// Protobuf-encoded public key of the provider.
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl Receipt {
+    pub fn sign(keypair: &Keypair, task_id: &str, requester_id: &str, status: &str, duration: Duration) -> Option<Self> {
+        let mut receipt = Receipt {
+            task_id: task_id.to_string(),
+            requester_id: requester_id.to_string(),
+            provider_id: PeerId::from(keypair.public()).to_string(),
+            status: status.to_string(),
+            duration_ms: duration.as_millis() as u64,
+            timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+            public_key: keypair.public().encode_protobuf(),
+            signature: Vec::new(),
+        };
+        receipt.signature = keypair.sign(&receipt.signed_bytes()).ok()?;
+        Some(receipt)
+    }
+
+    /// Checks the signature and that the key belongs to `provider_id`.
+    pub fn verify(&self) -> bool {
+        let key = match PublicKey::try_decode_protobuf(&self.public_key) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+        PeerId::from(key.clone()).to_string() == self.provider_id && key.verify(&self.signed_bytes(), &self.signature)
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        format!(
+            "meshclaw-receipt\n{}\n{}\n{}\n{}\n{}\n{}",
+            self.task_id, self.requester_id, self.provider_id, self.status, self.duration_ms, self.timestamp
+        )
+        .into_bytes()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskCounts {
+    pub successes: u64,
+    pub failures: u64,
+    pub total_latency_ms: u64,
+}
+
+impl TaskCounts {
+    fn record(&mut self, success: bool, latency: Duration) {
+        if success {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+        self.total_latency_ms += latency.as_millis() as u64;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.successes + self.failures
+    }
+
+    pub fn avg_latency_ms(&self) -> Option<u64> {
+        self.total_latency_ms.checked_div(self.total())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerAccount {
+    /// Tasks we delegated to this peer.
+    pub delegated: TaskCounts,
+    /// Tasks this peer delegated to us and we ran.
+    pub served: TaskCounts,
+    /// Tasks from this peer we turned down for lack of reciprocity.
+    pub refused: u64,
+    /// Tokens our backends generated or read for this peer's tasks.
+    #[serde(default)]
+    pub served_tokens: u64,
+    #[serde(default)]
+    pub receipts: Vec<Receipt>,
+}
+
+/// Local record of work exchanged with each peer, keyed by peer ID.
+pub struct Ledger {
+    local_id: String,
+    path: Option<PathBuf>,
+    allowance: Option<u64>,
+    accounts: RwLock<HashMap<String, PeerAccount>>,
+    dirty: AtomicBool,
+}
+
+impl Ledger {
+    pub fn new(local_id: String, path: Option<PathBuf>, allowance: Option<u64>) -> Self {
+        let accounts = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Ledger { local_id, path, allowance, accounts: RwLock::new(accounts), dirty: AtomicBool::new(false) }
+    }
+
+    /// Persists to `MESHCLAW_LEDGER_PATH` (default `./.meshclaw/ledger.json`).
+    /// `MESHCLAW_RECIPROCITY_ALLOWANCE` caps how many more tasks we serve for a
+    /// peer than it has served for us; unset means no limit.
+    pub fn load(local_id: String) -> Self {
+        let path = std::env::var("MESHCLAW_LEDGER_PATH").unwrap_or_else(|_| "./.meshclaw/ledger.json".to_string());
+        let allowance = std::env::var("MESHCLAW_RECIPROCITY_ALLOWANCE").ok().and_then(|v| v.parse().ok());
+        Ledger::new(local_id, Some(PathBuf::from(path)), allowance)
+    }
+
+    /// Writes the ledger if it changed since the last save.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec_pretty(&*self.accounts.read().unwrap())?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    fn update(&self, peer: &str, f: impl FnOnce(&mut PeerAccount)) {
+        if peer == self.local_id {
+            return;
+        }
+        f(self.accounts.write().unwrap().entry(peer.to_string()).or_default());
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Records the outcome of a task we delegated to `peer`. A receipt is kept
+    /// only if it verifies and was issued by that peer for the same task.
+    pub fn record_delegated(&self, peer: &str, task_id: &str, success: bool, latency: Duration, receipt: Option<Receipt>) {
+        let receipt = receipt.filter(|r| r.provider_id == peer && r.task_id == task_id && r.verify());
+        self.update(peer, |account| {
+            account.delegated.record(success, latency);
+            if let Some(receipt) = receipt {
+                account.receipts.push(receipt);
+                if account.receipts.len() > MAX_RECEIPTS {
+                    account.receipts.remove(0);
+                }
+            }
+        });
+    }
+
+    pub fn record_served(&self, requester: &str, success: bool, latency: Duration, tokens: u64) {
+        self.update(requester, |account| {
+            account.served.record(success, latency);
+            account.served_tokens += tokens;
+        });
+    }
+
+    pub fn record_refused(&self, requester: &str) {
+        self.update(requester, |account| account.refused += 1);
+    }
+
+    /// Whether we should run another task for `requester`.
+    pub fn permits(&self, requester: &str) -> bool {
+        let allowance = match self.allowance {
+            Some(allowance) if requester != self.local_id => allowance,
+            _ => return true,
+        };
+        let accounts = self.accounts.read().unwrap();
+        match accounts.get(requester) {
+            Some(account) => account.served.total() < account.delegated.successes + allowance,
+            None => allowance > 0,
+        }
+    }
+
+    /// Average time we took to serve tasks for other peers.
+    pub fn avg_served_latency_ms(&self) -> Option<u64> {
+        let accounts = self.accounts.read().unwrap();
+        let (total_ms, count) = accounts
+            .values()
+            .fold((0, 0), |(ms, n), a| (ms + a.served.total_latency_ms, n + a.served.total()));
+        total_ms.checked_div(count)
+    }
+
+    pub fn account(&self, peer: &str) -> Option<PeerAccount> {
+        self.accounts.read().unwrap().get(peer).cloned()
+    }
+
+    pub fn accounts(&self) -> HashMap<String, PeerAccount> {
+        self.accounts.read().unwrap().clone()
+    }
+}
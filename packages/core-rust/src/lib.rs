@@ -1,2 +1,17 @@
+pub mod approval;
+pub mod auction;
+pub mod cache;
+pub mod capability;
+pub mod doc_sync;
+pub mod graph;
+pub mod inference;
+pub mod ledger;
+pub mod plugins;
+pub mod provenance;
+pub mod quota;
+pub mod scheduler;
+pub mod stream;
 pub mod sync;
+pub mod tools;
 pub mod vector_db;
+pub mod voting;
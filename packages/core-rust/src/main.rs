@@ -8,8 +8,10 @@ use libp2p::kad;
 use libp2p::{
     multiaddr::Protocol, 
     PeerId, 
+    StreamProtocol,
 };
 use libp2p::gossipsub;
+use libp2p::request_response;
 use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
 use libp2p::tcp;
 use libp2p::yamux;
@@ -17,14 +19,39 @@ use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio;
-use meshclaw_core::sync::{MemorySync, SyncMessage};
+use meshclaw_core::approval::ApprovalGate;
+use meshclaw_core::cache::ResultCache;
+use meshclaw_core::capability::{self, CapabilityInfo, CapabilitySet};
+use meshclaw_core::doc_sync::{self, DocSync, DocSyncRequest, DocSyncResponse};
+use meshclaw_core::inference::{InferenceRouter, DEFAULT_CAPABILITY};
+use meshclaw_core::ledger::Ledger;
+use meshclaw_core::plugins::PluginHost;
+use meshclaw_core::provenance::UpdateEnvelope;
+use meshclaw_core::quota::QuotaTracker;
+use meshclaw_core::scheduler::Scheduler;
+use meshclaw_core::sync::{DocManager, SyncMessage};
+use meshclaw_core::tools::ToolRegistry;
 
 mod ws_bridge;
 mod bluetooth;
 mod lora;
+mod agent;
+mod requester;
+
+use agent::LocalAgent;
+use requester::Requester;
 
 use meshclaw_core::vector_db::VectorDb;
 
+// Capability provider records expire unless the provider keeps republishing them.
+const PROVIDER_RECORD_TTL: Duration = Duration::from_secs(30 * 60);
+const PROVIDER_REPUBLISH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const CAPABILITY_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+// Document update logs are folded into snapshots this often.
+const DOC_COMPACTION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often keys past their TTL are removed from shared memory.
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "MeshBehaviourEvent")]
 struct MeshBehaviour {
@@ -33,6 +60,7 @@ struct MeshBehaviour {
     identify: identify::Behaviour,
     gossipsub: gossipsub::Behaviour,
     kad: kad::Behaviour<kad::store::MemoryStore>,
+    doc_sync: request_response::cbor::Behaviour<DocSyncRequest, DocSyncResponse>,
 }
 
 #[derive(Debug)]
@@ -42,6 +70,7 @@ enum MeshBehaviourEvent {
     Identify(identify::Event),
     Gossipsub(gossipsub::Event),
     Kad(kad::Event),
+    DocSync(request_response::Event<DocSyncRequest, DocSyncResponse>),
 }
 
 impl From<mdns::Event> for MeshBehaviourEvent {
@@ -74,13 +103,19 @@ impl From<kad::Event> for MeshBehaviourEvent {
     }
 }
 
+impl From<request_response::Event<DocSyncRequest, DocSyncResponse>> for MeshBehaviourEvent {
+    fn from(event: request_response::Event<DocSyncRequest, DocSyncResponse>) -> Self {
+        MeshBehaviourEvent::DocSync(event)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    let local_key = identity::Keypair::generate_ed25519();
+    let local_key = load_identity()?;
     let local_peer_id = PeerId::from(local_key.public());
     println!("Local peer id: {local_peer_id}");
 
@@ -113,19 +148,31 @@ async fn main() -> Result<(), Box<dyn Error>> {
             let store = kad::store::MemoryStore::new(local_peer_id);
             let mut kad_cfg = kad::Config::default();
             kad_cfg.set_query_timeout(Duration::from_secs(30));
+            kad_cfg.set_provider_record_ttl(Some(PROVIDER_RECORD_TTL));
+            kad_cfg.set_provider_publication_interval(Some(PROVIDER_REPUBLISH_INTERVAL));
             let kad = kad::Behaviour::with_config(local_peer_id, store, kad_cfg);
 
-            Ok(MeshBehaviour { mdns, ping, identify, gossipsub, kad })
+            let doc_sync = request_response::cbor::Behaviour::new(
+                [(StreamProtocol::new(doc_sync::PROTOCOL), request_response::ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(Duration::from_secs(60)),
+            );
+
+            Ok(MeshBehaviour { mdns, ping, identify, gossipsub, kad, doc_sync })
         })?
         .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
         .build();
 
     swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
 
-    let memory = Arc::new(MemorySync::new());
-    memory.insert_text("note1", "Initial shared knowledge");
+    let docs = Arc::new(DocManager::from_env(local_key.clone())?);
+    let memory = docs.default_doc();
+    memory.update_text("note1", "Initial shared knowledge")?;
+    let mut doc_sync = DocSync::new(docs.clone());
 
     let peers = Arc::new(tokio::sync::RwLock::new(std::collections::HashSet::<String>::new()));
+    let ledger = Arc::new(Ledger::load(local_peer_id.to_string()));
+    let quotas = Arc::new(QuotaTracker::from_env(local_peer_id.to_string()));
+    let approvals = Arc::new(ApprovalGate::from_env());
 
     // Initialize stubs
     bluetooth::init();
@@ -135,29 +182,67 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let (gateway_to_rust_tx, mut gateway_to_rust_rx) = tokio::sync::mpsc::channel::<SyncMessage>(100);
     let (rust_to_gateway_tx, _) = tokio::sync::broadcast::channel::<serde_json::Value>(100);
     
-    let mem_clone = memory.clone();
+    let bridge_docs = docs.clone();
     let peers_clone = peers.clone();
     let bridge_tx = rust_to_gateway_tx.clone();
+    let bridge_ledger = ledger.clone();
+    let bridge_quotas = quotas.clone();
+    let bridge_approvals = approvals.clone();
+    let delegate_tx = gateway_to_rust_tx.clone();
     tokio::spawn(async move {
-        ws_bridge::run_ws_server(mem_clone, gateway_to_rust_tx, bridge_tx, peers_clone).await;
+        ws_bridge::run_ws_server(bridge_docs, gateway_to_rust_tx, bridge_tx, peers_clone, bridge_ledger, bridge_quotas, bridge_approvals).await;
     });
 
-    let vector_db = VectorDb::new().await;
+    // Key-level changes to shared memory, for bridge clients subscribed to them.
+    let mut key_changes = docs.subscribe_changes();
+    let changes_tx = rust_to_gateway_tx.clone();
+    let local_id = local_peer_id.to_string();
+    tokio::spawn(async move {
+        loop {
+            match key_changes.recv().await {
+                Ok(mut change) => {
+                    change.origin.get_or_insert_with(|| local_id.clone());
+                    let _ = changes_tx.send(serde_json::json!({
+                        "type": "event",
+                        "method": "mesh:memory:changed",
+                        "params": change
+                    }));
+                },
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    eprintln!("Dropped {skipped} memory change events");
+                },
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let inference = Arc::new(InferenceRouter::load());
+    let tools = Arc::new(ToolRegistry::from_env());
+    let plugins = Arc::new(PluginHost::load(memory.clone(), inference.clone())?);
+
+    let cache = ResultCache::from_env()?.map(Arc::new);
+
+    let vector_db = Arc::new(VectorDb::new().await);
     vector_db.add("note1", vec![0.1; 384], "Initial knowledge").await;
 
-    // Multi-capability registration
-    let capabilities = vec!["llm:llama3", "tool:websearch", "memory:lancedb"];
-    for cap in capabilities {
-        let key = kad::RecordKey::from(format!("cap:{}", cap).into_bytes());
-        let value = serde_json::to_vec(&serde_json::json!({
-            "peer": local_peer_id.to_string(),
-            "timestamp": std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
-        })).unwrap();
-        let record = kad::Record { key, value, publisher: Some(local_peer_id), expires: None };
-        if let Err(e) = swarm.behaviour_mut().kad.put_record(record, kad::Quorum::One) {
-            eprintln!("Failed to put record for {}: {}", cap, e);
+    // Capabilities are probed at startup and re-probed periodically, so
+    // advertisements follow what this node can actually serve.
+    let (probe_tx, mut probe_rx) = tokio::sync::mpsc::channel::<Vec<CapabilityInfo>>(4);
+    let probe_inference = inference.clone();
+    let probe_tools = tools.clone();
+    let probe_plugins = plugins.clone();
+    let probe_vector_db = vector_db.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CAPABILITY_PROBE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let probed = capability::probe(&probe_inference, &probe_tools, &probe_plugins, &probe_vector_db).await;
+            if probe_tx.send(probed).await.is_err() {
+                break;
+            }
         }
-    }
+    });
+    let mut capabilities = CapabilitySet::new();
 
     // Kademlia Bootstrap
     if let Err(e) = swarm.behaviour_mut().kad.bootstrap() {
@@ -168,20 +253,86 @@ async fn main() -> Result<(), Box<dyn Error>> {
     swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
 
     let mut broadcast_timer = tokio::time::interval(Duration::from_secs(10));
-    let mut pending_delegations = std::collections::HashMap::<kad::QueryId, SyncMessage>::new();
+    let mut compaction_timer = tokio::time::interval(DOC_COMPACTION_INTERVAL);
+    let mut snapshot_timer = tokio::time::interval(docs.snapshot_interval());
+    let mut expiry_timer = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+    // A cache lookup carries the capability key to fall back to when no node has the result.
+    let mut pending_delegations = std::collections::HashMap::<kad::QueryId, (SyncMessage, std::collections::HashSet<PeerId>, Option<String>)>::new();
+    let (task_tx, mut task_rx) = tokio::sync::mpsc::channel::<SyncMessage>(256);
+    let agent = LocalAgent::new(
+        inference.clone(),
+        tools.clone(),
+        plugins.clone(),
+        memory.clone(),
+        cache.clone(),
+        ledger.clone(),
+        quotas,
+        approvals,
+        local_key.clone(),
+        task_tx,
+        rust_to_gateway_tx.clone(),
+    );
+    let (auction_tx, mut auction_rx) = tokio::sync::mpsc::channel::<String>(64);
+    let mut scheduler = Scheduler::from_env();
+    scheduler.record_rtt(local_peer_id, Duration::ZERO);
+    for (peer, account) in ledger.accounts() {
+        if let Ok(peer) = peer.parse::<PeerId>() {
+            scheduler.record_history(peer, account.delegated.successes as u32, account.delegated.failures as u32);
+        }
+    }
+    let mut requester = Requester::new(
+        scheduler,
+        ledger.clone(),
+        cache.clone(),
+        inference.clone(),
+        memory.clone(),
+        auction_tx,
+        delegate_tx,
+        rust_to_gateway_tx.clone(),
+    );
 
     loop {
         tokio::select! {
             bridge_msg = gateway_to_rust_rx.recv() => {
-                if let Some(msg) = bridge_msg {
+                if let Some(mut msg) = bridge_msg {
+                    stamp_requester(&mut msg, local_peer_id);
+                    if let SyncMessage::Delegate { task_id, assignee_id, .. } = &msg {
+                        requester.track(task_id, assignee_id.parse::<PeerId>().ok());
+                    }
                     match &msg {
-                        SyncMessage::Delegate { assignee_id, task_desc, .. } if assignee_id == "any" || assignee_id.starts_with("cap:") => {
-                            // Find provider via DHT
-                            let cap_query = if assignee_id == "any" { "cap:llm:llama3" } else { assignee_id };
-                            println!("Searching DHT for capability: {}", cap_query);
-                            let query_id = swarm.behaviour_mut().kad.get_record(kad::RecordKey::from(cap_query.as_bytes().to_vec()));
-                            pending_delegations.insert(query_id, msg.clone());
+                        SyncMessage::Delegate { assignee_id, .. } if *assignee_id == local_peer_id.to_string() => {
+                            agent.run(msg.clone(), &local_peer_id.to_string());
+                        },
+                        SyncMessage::Delegate { task_id, assignee_id, .. } if assignee_id == "any" || assignee_id.starts_with("cap:") => {
+                            let cap_query = if assignee_id == "any" { format!("cap:{}", DEFAULT_CAPABILITY) } else { assignee_id.clone() };
+                            let mut routed = msg.clone();
+                            if let SyncMessage::Delegate { ref mut capability, .. } = routed {
+                                capability.get_or_insert_with(|| cap_query.trim_start_matches("cap:").to_string());
+                            }
+                            // Deterministic requests first look for a node that already has the answer.
+                            match requester.cache_key(&routed) {
+                                Some(key) if requester.answer_from_cache(task_id, &key) => {},
+                                Some(key) => {
+                                    println!("Searching DHT for cached result: {}", key);
+                                    let query_id = swarm.behaviour_mut().kad.get_providers(kad::RecordKey::from(format!("cache:{}", key).into_bytes()));
+                                    pending_delegations.insert(query_id, (routed, std::collections::HashSet::new(), Some(cap_query)));
+                                },
+                                None => match requester.start_auction(&routed) {
+                                    Some(cfp) => open_auction(cfp, &mut requester, &agent, &capabilities, &mut swarm, &topic),
+                                    None => {
+                                        // Find provider via DHT
+                                        println!("Searching DHT for capability: {}", cap_query);
+                                        let query_id = swarm.behaviour_mut().kad.get_providers(kad::RecordKey::from(cap_query.as_bytes().to_vec()));
+                                        pending_delegations.insert(query_id, (routed, std::collections::HashSet::new(), None));
+                                    },
+                                },
+                            }
                         },
+                        SyncMessage::TaskGraph { graph_id, requester_id, steps, .. } => {
+                            requester.start_graph(graph_id.clone(), requester_id.clone(), steps.clone());
+                        },
+                        // Already applied by the bridge; it goes out with the next tick's local changes.
+                        SyncMessage::MemorySync { .. } => {},
                         _ => {
                             if let Ok(data) = serde_json::to_vec(&msg) {
                                 if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
@@ -192,21 +343,144 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     }
                 }
             }
+            task_msg = task_rx.recv() => {
+                if let Some(msg) = task_msg {
+                    if !requester.handle(&msg, Some(local_peer_id)) {
+                        if let Ok(data) = serde_json::to_vec(&msg) {
+                            if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                                eprintln!("Task publish error: {e}");
+                            }
+                        }
+                    }
+                }
+            }
+            closed = auction_rx.recv() => {
+                if let Some(task_id) = closed {
+                    requester.scheduler.record_load(local_peer_id, agent.queue_depth());
+                    let assigned = requester.close_auction(&task_id);
+                    send_assigned(assigned, local_peer_id, &agent, &mut swarm, &topic);
+                }
+            }
+            probed = probe_rx.recv() => {
+                if let Some(probed) = probed {
+                    let changes = capabilities.apply(probed);
+                    let mut updates = Vec::new();
+                    for cap in changes.announced {
+                        println!("Advertising capability {}", cap.name);
+                        let key = kad::RecordKey::from(format!("cap:{}", cap.name).into_bytes());
+                        if let Err(e) = swarm.behaviour_mut().kad.start_providing(key) {
+                            eprintln!("Failed to announce provider record for {}: {}", cap.name, e);
+                        }
+                        updates.push(SyncMessage::Capability {
+                            node_id: local_peer_id.to_string(),
+                            capability: cap.name,
+                            metadata: cap.metadata,
+                        });
+                    }
+                    for name in changes.withdrawn {
+                        println!("Withdrawing capability {}", name);
+                        let key = kad::RecordKey::from(format!("cap:{}", name).into_bytes());
+                        swarm.behaviour_mut().kad.stop_providing(&key);
+                        updates.push(SyncMessage::Capability {
+                            node_id: local_peer_id.to_string(),
+                            capability: name,
+                            metadata: serde_json::json!({ "withdrawn": true }),
+                        });
+                    }
+                    if !updates.is_empty() {
+                        for msg in &updates {
+                            if let Ok(data) = serde_json::to_vec(msg) {
+                                if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                                    eprintln!("Capability publish error: {e}");
+                                }
+                            }
+                        }
+                        let _ = rust_to_gateway_tx.send(serde_json::json!({
+                            "type": "event",
+                            "method": "mesh:capabilities",
+                            "params": { "nodeId": local_peer_id.to_string(), "capabilities": capabilities.list() }
+                        }));
+                    }
+                }
+            }
             _ = broadcast_timer.tick() => {
-                let msg = SyncMessage::MemorySync {
-                    doc_id: "note1".to_string(),
-                    delta: memory.get_update(),
-                    version: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+                requester.sweep(std::time::Instant::now());
+                if let Some(cache) = &cache {
+                    match cache.evict() {
+                        Ok(evicted) => {
+                            for key in evicted {
+                                let record = kad::RecordKey::from(format!("cache:{}", key).into_bytes());
+                                swarm.behaviour_mut().kad.stop_providing(&record);
+                            }
+                        },
+                        Err(e) => eprintln!("Failed to evict cached results: {e}"),
+                    }
+                    for key in cache.take_unannounced() {
+                        let record = kad::RecordKey::from(format!("cache:{}", key).into_bytes());
+                        if let Err(e) = swarm.behaviour_mut().kad.start_providing(record) {
+                            eprintln!("Failed to announce cached result {}: {}", key, e);
+                        }
+                    }
+                }
+                if let Err(e) = ledger.save() {
+                    eprintln!("Failed to save ledger: {e}");
+                }
+                let load = SyncMessage::Load {
+                    node_id: local_peer_id.to_string(),
+                    queue_depth: agent.queue_depth(),
+                    timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
                 };
-                if let Ok(data) = serde_json::to_vec(&msg) {
+                if let Ok(data) = serde_json::to_vec(&load) {
                     if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
-                        eprintln!("Publish error: {e}");
+                        eprintln!("Load publish error: {e}");
                     }
                 }
+
+                // Only changes made here since the last tick; peers that miss
+                // one pull what they lack over direct sync.
+                for doc_id in docs.list() {
+                    let (delta, client_id) = match docs.get(&doc_id).map(|doc| (doc.take_changes(), doc.client_id())) {
+                        Some((Ok(Some(delta)), client_id)) => (delta, client_id),
+                        Some((Err(e), _)) => {
+                            eprintln!("Failed to collect changes of '{doc_id}': {e}");
+                            continue;
+                        },
+                        _ => continue,
+                    };
+                    let envelope = UpdateEnvelope::sign(&local_key, &doc_id, client_id, &delta);
+                    let msg = SyncMessage::MemorySync {
+                        doc_id,
+                        delta,
+                        version: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+                        envelope,
+                    };
+                    if let Ok(data) = serde_json::to_vec(&msg) {
+                        if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                            eprintln!("Publish error: {e}");
+                        }
+                    }
+                }
+            }
+            _ = compaction_timer.tick() => {
+                if let Err(e) = docs.compact() {
+                    eprintln!("{e:#}");
+                }
+            }
+            _ = expiry_timer.tick() => {
+                if let Err(e) = docs.sweep_expired() {
+                    eprintln!("{e:#}");
+                }
+            }
+            _ = snapshot_timer.tick() => {
+                if let Err(e) = docs.snapshot() {
+                    eprintln!("{e:#}");
+                }
             }
             _ = tokio::signal::ctrl_c() => {
                 println!("Shutting down...");
-                memory.save();
+                if let Err(e) = docs.compact() {
+                    eprintln!("{e:#}");
+                }
                 break;
             }
             event = swarm.select_next_some() => match event {
@@ -239,105 +513,186 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     }) => {
                         if let Ok(sync_msg) = serde_json::from_slice::<SyncMessage>(&message.data) {
                             let _ = rust_to_gateway_tx.send(serde_json::to_value(&sync_msg).unwrap_or_default());
+                            let origin = message.source.unwrap_or(peer_id).to_string();
+                            // A signed update maps its author's yrs client to them before it
+                            // is applied; unsigned ones, ones whose signature does not hold and
+                            // ones carrying changes under another peer's clients are dropped.
+                            let authentic = match &sync_msg {
+                                SyncMessage::MemorySync { doc_id, delta, envelope: Some(envelope), .. } => {
+                                    let checked = if envelope.peer_id != origin || !envelope.verify(doc_id, delta) {
+                                        Err(anyhow::anyhow!("invalid envelope"))
+                                    } else {
+                                        docs.open_doc(doc_id).map_err(anyhow::Error::from).and_then(|doc| {
+                                            doc.check_clients(&envelope.peer_id, envelope.client_id, delta)?;
+                                            doc.attribute(&envelope.peer_id, envelope.client_id)
+                                        })
+                                    };
+                                    if let Err(e) = &checked {
+                                        eprintln!("Dropping memory update for '{doc_id}' from {origin}: {e}");
+                                    }
+                                    checked.is_ok()
+                                },
+                                SyncMessage::MemorySync { doc_id, envelope: None, .. } => {
+                                    eprintln!("Dropping memory update for '{doc_id}' from {origin}: unsigned");
+                                    false
+                                },
+                                _ => true,
+                            };
                             match sync_msg {
-                                SyncMessage::MemorySync { delta, .. } => {
-                                    println!("Received memory update from {peer_id:?}");
-                                    if let Err(e) = memory.apply_update(delta) {
-                                        eprintln!("Apply update failed: {e}");
-                                    } else if let Some(text) = memory.get_text("note1") {
-                                        println!("Current shared note1: {text}");
+                                SyncMessage::MemorySync { .. } if !authentic => {},
+                                SyncMessage::MemorySync { doc_id, delta, .. } => {
+                                    println!("Received memory update for '{doc_id}' from {peer_id:?}");
+                                    match docs.open_doc(&doc_id).map_err(anyhow::Error::from).and_then(|doc| doc.apply_remote(&delta, &origin, true)) {
+                                        // Fetch the changes it depends on directly from the peer that relayed it.
+                                        Ok(true) => {
+                                            if let Some(pull) = doc_sync.pull(&peer_id.to_string(), &doc_id) {
+                                                swarm.behaviour_mut().doc_sync.send_request(&peer_id, pull);
+                                            }
+                                        },
+                                        Ok(false) => {
+                                            if let Some(text) = docs.get(&doc_id).and_then(|doc| doc.get_text("note1")) {
+                                                println!("Current shared note1: {text}");
+                                            }
+                                        },
+                                        Err(e) => eprintln!("Apply update failed: {e}"),
+                                    }
+                                },
+                                SyncMessage::DocRecord { record } => {
+                                    let action = if record.deleted { "Deleted" } else { "Created" };
+                                    match docs.apply_record(&record) {
+                                        Ok(true) => println!("{action} document '{}' as signed by {}", record.doc_id, record.peer_id),
+                                        Ok(false) => {},
+                                        Err(e) => eprintln!("Ignoring record for document '{}' from {origin}: {e}", record.doc_id),
                                     }
                                 },
                                 SyncMessage::KnowledgeUpdate { key, value } => {
                                     println!("Received high-level knowledge update for '{}'", key);
-                                    memory.insert_text(&key, &value);
+                                    if let Err(e) = memory.update_text(&key, &value) {
+                                        eprintln!("Failed to store knowledge update: {e}");
+                                    }
                                 },
                                 SyncMessage::Query(q) => {
                                     println!("Received query from {peer_id:?}: {q}");
                                 },
-                                SyncMessage::Delegate { task_id, task_desc, assignee_id, .. } => {
-                                    if assignee_id == local_peer_id.to_string() {
-                                        println!("🦞 Local Agent: Processing task '{}' via Ollama [{}]", task_desc, task_id);
-                                        
-                                        let rust_to_gateway_tx = rust_to_gateway_tx.clone();
-                                        let task_id_clone = task_id.clone();
-                                        let prompt = task_desc.clone();
-
-                                        tokio::spawn(async move {
-                                            let client = reqwest::Client::new();
-                                            let ollama_url = "http://localhost:11434/api/generate";
-                                            
-                                            let payload = serde_json::json!({
-                                                "model": "llama3",
-                                                "prompt": prompt,
-                                                "stream": false
-                                            });
-
-                                            let result = match client.post(ollama_url).json(&payload).send().await {
-                                                Ok(resp) => {
-                                                    if let Ok(json) = resp.json::<serde_json::Value>().await {
-                                                        json["response"].as_str().unwrap_or("Empty response from Ollama").to_string()
-                                                    } else {
-                                                        "Failed to parse Ollama JSON".to_string()
-                                                    }
-                                                },
-                                                Err(e) => format!("Ollama connection failed: {}. Is Ollama running?", e)
-                                            };
-
-                                            println!("🦞 Local Agent: Task '{}' completed", task_id_clone);
-                                            let _ = rust_to_gateway_tx.send(serde_json::json!({
-                                                "type": "event",
-                                                "method": "mesh:agent:result",
-                                                "params": {
-                                                    "taskId": task_id_clone,
-                                                    "status": "completed",
-                                                    "result": result
+                                msg @ SyncMessage::Delegate { .. } => {
+                                    // Quotas are charged to the requester, so it must be the
+                                    // peer that signed the message.
+                                    match &msg {
+                                        SyncMessage::Delegate { assignee_id, .. } if *assignee_id != local_peer_id.to_string() => {},
+                                        SyncMessage::Delegate { task_id, requester_id, .. } if !from_requester(&msg, &origin) => {
+                                            eprintln!("Dropping task '{task_id}': requester {requester_id} did not publish it");
+                                        },
+                                        _ => agent.run(msg, &origin),
+                                    }
+                                }
+                                // Bids are sized to the requester's standing, so only answer
+                                // calls the requester published itself.
+                                SyncMessage::CallForProposals { task_id, requester_id, .. } if requester_id != origin => {
+                                    eprintln!("Ignoring call for proposals '{task_id}': requester {requester_id} did not publish it");
+                                }
+                                SyncMessage::CallForProposals { task_id, requester_id, capability, .. } => {
+                                    if capabilities.contains(&capability) {
+                                        if let Some(bid) = agent.bid(&requester_id) {
+                                            let reply = SyncMessage::Bid { task_id, requester_id, bid };
+                                            if let Ok(data) = serde_json::to_vec(&reply) {
+                                                if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                                                    eprintln!("Bid publish error: {e}");
                                                 }
-                                            }));
-                                        });
+                                            }
+                                        }
                                     }
                                 }
+                                SyncMessage::Bid { task_id, requester_id, bid } => {
+                                    if requester_id == local_peer_id.to_string() && bid.bidder_id == origin {
+                                        requester.record_bid(&task_id, bid);
+                                    }
+                                }
+                                // A node only reports its own load.
+                                SyncMessage::Load { node_id, .. } if node_id != origin => {
+                                    eprintln!("Dropping load report for {node_id} published by {origin}");
+                                }
+                                SyncMessage::Load { node_id, queue_depth, .. } => {
+                                    if let Ok(peer) = node_id.parse::<PeerId>() {
+                                        requester.scheduler.record_load(peer, queue_depth);
+                                    }
+                                }
+                                ref event @ (SyncMessage::TaskChunk { .. } | SyncMessage::TaskResult { .. } | SyncMessage::TaskStatus { .. }) => {
+                                    requester.handle(event, message.source);
+                                }
                                 _ => {}
                             }
                         }
                     }
-                    MeshBehaviourEvent::Kad(kad::Event::OutboundQueryProgressed { id, result, .. }) => {
+                    MeshBehaviourEvent::Kad(kad::Event::OutboundQueryProgressed { id, result, step, .. }) => {
                         match result {
-                            kad::QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(record))) => {
-                                if let Some(mut original_msg) = pending_delegations.remove(&id) {
-                                    if let SyncMessage::Delegate { ref mut assignee_id, .. } = original_msg {
-                                        if let Ok(val) = serde_json::from_slice::<serde_json::Value>(&record.record.value) {
-                                            if let Some(peer_str) = val.get("peer").and_then(|v| v.as_str()) {
-                                                println!("Found provider for task: {peer_str}");
-                                                *assignee_id = peer_str.to_string();
-                                                
-                                                if let Ok(data) = serde_json::to_vec(&original_msg) {
-                                                    if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
-                                                        eprintln!("DHT-routed publish error: {e}");
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
+                            kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders { providers, .. })) => {
+                                if let Some((_, found, _)) = pending_delegations.get_mut(&id) {
+                                    found.extend(providers);
                                 }
                             },
-                            kad::QueryResult::GetRecord(Err(e)) => {
-                                eprintln!("DHT lookup failed for query {:?}: {:?}", id, e);
-                                pending_delegations.remove(&id);
+                            kad::QueryResult::GetProviders(Err(e)) => {
+                                eprintln!("DHT provider lookup failed for query {:?}: {:?}", id, e);
                             },
                             _ => {}
                         }
+
+                        let finished = if step.last { pending_delegations.remove(&id) } else { None };
+                        match finished {
+                            Some((original_msg, found, Some(cap_query))) if found.is_empty() => {
+                                match requester.start_auction(&original_msg) {
+                                    Some(cfp) => open_auction(cfp, &mut requester, &agent, &capabilities, &mut swarm, &topic),
+                                    None => {
+                                        println!("No cached result found; searching DHT for capability: {}", cap_query);
+                                        let query_id = swarm.behaviour_mut().kad.get_providers(kad::RecordKey::from(cap_query.as_bytes().to_vec()));
+                                        pending_delegations.insert(query_id, (original_msg, std::collections::HashSet::new(), None));
+                                    },
+                                }
+                            },
+                            Some((original_msg, providers, _)) => {
+                                requester.scheduler.record_load(local_peer_id, agent.queue_depth());
+                                let assigned = requester.dispatch(original_msg, &providers);
+                                send_assigned(assigned, local_peer_id, &agent, &mut swarm, &topic);
+                            },
+                            None => {}
+                        }
                     }
                     MeshBehaviourEvent::Ping(ping::Event { peer, result, .. }) => {
                         println!("Ping to {peer}: {:?}", result);
+                        if let Ok(rtt) = result {
+                            requester.scheduler.record_rtt(peer, rtt);
+                        }
                     }
                     MeshBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. }) => {
                         println!("Identified peer {peer_id}: {info:?}");
                     }
+                    MeshBehaviourEvent::DocSync(request_response::Event::Message { peer, message, .. }) => match message {
+                        request_response::Message::Request { request, channel, .. } => {
+                            let response = doc_sync.answer(&peer.to_string(), request);
+                            if swarm.behaviour_mut().doc_sync.send_response(channel, response).is_err() {
+                                eprintln!("Doc sync: could not answer {peer}");
+                            }
+                        }
+                        request_response::Message::Response { response, .. } => {
+                            for next in doc_sync.receive(&peer.to_string(), response) {
+                                swarm.behaviour_mut().doc_sync.send_request(&peer, next);
+                            }
+                        }
+                    },
+                    MeshBehaviourEvent::DocSync(request_response::Event::OutboundFailure { peer, error, .. }) => {
+                        eprintln!("Doc sync with {peer} failed: {error}");
+                        doc_sync.forget(&peer.to_string());
+                    }
                     _ => {}
                 }
-                SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                SwarmEvent::ConnectionEstablished { peer_id, num_established, .. } => {
                     println!("Connected to {peer_id}");
+                    // Catch up directly instead of waiting for gossip, whatever the size of the docs.
+                    if num_established.get() == 1 {
+                        swarm.behaviour_mut().doc_sync.send_request(&peer_id, doc_sync.hello());
+                    }
+                }
+                SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                    doc_sync.forget(&peer_id.to_string());
                 }
                 _ => {}
             }
@@ -345,3 +700,163 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
     Ok(())
 }
+
+/// Runs delegates assigned to this node and publishes the rest to their assignees.
+fn send_assigned(
+    msgs: Vec<SyncMessage>,
+    local_peer_id: PeerId,
+    agent: &LocalAgent,
+    swarm: &mut libp2p::Swarm<MeshBehaviour>,
+    topic: &gossipsub::IdentTopic,
+) {
+    for msg in msgs {
+        let provider = match &msg {
+            SyncMessage::Delegate { assignee_id, .. } => assignee_id.clone(),
+            _ => continue,
+        };
+        if provider == local_peer_id.to_string() {
+            println!("Found provider for task: {provider} (local)");
+            agent.run(msg, &local_peer_id.to_string());
+        } else {
+            println!("Found provider for task: {provider}");
+            if let Ok(data) = serde_json::to_vec(&msg) {
+                if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                    eprintln!("DHT-routed publish error: {e}");
+                }
+            }
+        }
+    }
+}
+
+/// Requests from our gateway are made on behalf of this node, so they name
+/// it as the requester whatever the gateway put there (e.g. `dashboard-ui`);
+/// providers check that name against the peer that published the request,
+/// and graph steps and calls for proposals inherit it.
+fn stamp_requester(msg: &mut SyncMessage, local_peer_id: PeerId) {
+    match msg {
+        SyncMessage::Delegate { requester_id, .. }
+        | SyncMessage::TaskGraph { requester_id, .. }
+        | SyncMessage::CallForProposals { requester_id, .. } => *requester_id = local_peer_id.to_string(),
+        _ => {}
+    }
+}
+
+/// Whether a delegate or call for proposals was published by the peer it
+/// names as requester.
+fn from_requester(msg: &SyncMessage, source: &str) -> bool {
+    match msg {
+        SyncMessage::Delegate { requester_id, .. } | SyncMessage::CallForProposals { requester_id, .. } => requester_id == source,
+        _ => true,
+    }
+}
+
+/// Loads the node's keypair from `MESHCLAW_IDENTITY_PATH` (default
+/// `./.meshclaw/identity.key`), generating and saving one on first start, so
+/// the peer ID that ledgers, quotas and provenance are keyed on survives restarts.
+fn load_identity() -> Result<identity::Keypair, Box<dyn Error>> {
+    let path = std::env::var("MESHCLAW_IDENTITY_PATH").unwrap_or_else(|_| "./.meshclaw/identity.key".to_string());
+    match std::fs::read(&path) {
+        Ok(bytes) => return Ok(identity::Keypair::from_protobuf_encoding(&bytes)?),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        Err(_) => {},
+    }
+    let keypair = identity::Keypair::generate_ed25519();
+    if let Some(dir) = std::path::Path::new(&path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, keypair.to_protobuf_encoding()?)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(keypair)
+}
+
+/// Broadcasts a call for proposals and enters our own bid if we serve the capability,
+/// since gossipsub does not deliver our own messages back to us.
+fn open_auction(
+    cfp: SyncMessage,
+    requester: &mut Requester,
+    agent: &LocalAgent,
+    capabilities: &CapabilitySet,
+    swarm: &mut libp2p::Swarm<MeshBehaviour>,
+    topic: &gossipsub::IdentTopic,
+) {
+    if let SyncMessage::CallForProposals { task_id, requester_id, capability, .. } = &cfp {
+        if capabilities.contains(capability) {
+            if let Some(bid) = agent.bid(requester_id) {
+                requester.record_bid(task_id, bid);
+            }
+        }
+    }
+    if let Ok(data) = serde_json::to_vec(&cfp) {
+        if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+            eprintln!("Call for proposals publish error: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> PeerId {
+        PeerId::from(identity::Keypair::generate_ed25519().public())
+    }
+
+    /// A dashboard delegate as the gateway sends it, naming itself as requester.
+    fn gateway_delegate(assignee: &str) -> SyncMessage {
+        serde_json::from_value(serde_json::json!({
+            "type": "delegate",
+            "taskId": "task-1",
+            "taskDesc": "summarise",
+            "requesterId": "dashboard-ui",
+            "assigneeId": assignee,
+            "payload": {},
+            "timestamp": 0
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn remote_provider_accepts_gateway_delegate_published_by_its_node() {
+        let (requester, provider) = (peer(), peer());
+        let mut msg = gateway_delegate(&provider.to_string());
+        assert!(!from_requester(&msg, &requester.to_string()));
+
+        stamp_requester(&mut msg, requester);
+        // The provider sees the message with the requester's node as gossip source.
+        let received: SyncMessage = serde_json::from_slice(&serde_json::to_vec(&msg).unwrap()).unwrap();
+        assert!(from_requester(&received, &requester.to_string()));
+        assert!(!from_requester(&received, &peer().to_string()));
+    }
+
+    #[test]
+    fn graphs_and_calls_for_proposals_name_the_local_node() {
+        let requester = peer();
+        let mut graph: SyncMessage = serde_json::from_value(serde_json::json!({
+            "type": "task-graph",
+            "graphId": "g",
+            "requesterId": "dashboard-ui",
+            "steps": [],
+            "timestamp": 0
+        }))
+        .unwrap();
+        stamp_requester(&mut graph, requester);
+        match graph {
+            SyncMessage::TaskGraph { requester_id, .. } => assert_eq!(requester_id, requester.to_string()),
+            _ => unreachable!(),
+        }
+
+        let mut cfp = SyncMessage::CallForProposals {
+            task_id: "task-1".to_string(),
+            requester_id: "dashboard-ui".to_string(),
+            capability: "llm:llama3".to_string(),
+            deadline_ms: 2000,
+            timestamp: 0,
+        };
+        stamp_requester(&mut cfp, requester);
+        assert!(from_requester(&cfp, &requester.to_string()));
+    }
+}
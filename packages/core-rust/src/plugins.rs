@@ -0,0 +1,342 @@
+use crate::inference::InferenceRouter;
+use crate::sync::MemorySync;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use wasmtime::{Caller, Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+fn default_fuel() -> u64 {
+    100_000_000
+}
+
+fn default_max_memory_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MemoryPermissions {
+    #[serde(default)]
+    pub read: Vec<String>,
+    #[serde(default)]
+    pub write: Vec<String>,
+}
+
+/// `<name>.json` next to `<name>.wasm` in the plugin directory.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginManifest {
+    pub name: String,
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub memory: MemoryPermissions,
+    /// Inference capabilities the plugin may call, e.g. `llm:llama3`.
+    #[serde(default)]
+    pub inference: Vec<String>,
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+    #[serde(default = "default_max_memory_bytes")]
+    pub max_memory_bytes: usize,
+}
+
+/// Entries ending in `*` are key prefixes; anything else must match exactly.
+fn permitted(patterns: &[String], key: &str) -> bool {
+    patterns.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => key.starts_with(prefix),
+        None => p == key,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub enum PluginError {
+    UnknownCapability(String),
+    Load(String),
+    Trap(String),
+    InvalidOutput(String),
+}
+
+impl PluginError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PluginError::UnknownCapability(_) => "unknown-capability",
+            PluginError::Load(_) => "load",
+            PluginError::Trap(_) => "trap",
+            PluginError::InvalidOutput(_) => "invalid-output",
+        }
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::UnknownCapability(cap) => write!(f, "no plugin provides '{}'", cap),
+            PluginError::Load(e) => write!(f, "failed to instantiate plugin: {}", e),
+            PluginError::Trap(e) => write!(f, "plugin trapped: {}", e),
+            PluginError::InvalidOutput(e) => write!(f, "invalid plugin output: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+struct Plugin {
+    manifest: PluginManifest,
+    module: Module,
+}
+
+struct HostState {
+    manifest: PluginManifest,
+    limits: StoreLimits,
+    memory: Arc<MemorySync>,
+    inference: Arc<InferenceRouter>,
+    runtime: tokio::runtime::Handle,
+}
+
+/// Loads sandboxed WebAssembly plugins and runs them for the capabilities
+/// they declare.
+///
+/// Guest ABI: the module exports `memory`, `alloc(len: i32) -> i32` and
+/// `run(cap_ptr, cap_len, input_ptr, input_len) -> i64`, which returns the
+/// output JSON as `(ptr << 32) | len`, or a negative value on failure. Host
+/// functions are imported from the `meshclaw` module:
+/// `log(ptr, len)`, `memory_get(key_ptr, key_len) -> i64`,
+/// `memory_set(key_ptr, key_len, val_ptr, val_len) -> i32` and
+/// `infer(cap_ptr, cap_len, prompt_ptr, prompt_len) -> i64`.
+pub struct PluginHost {
+    engine: Engine,
+    plugins: HashMap<String, Arc<Plugin>>,
+    memory: Arc<MemorySync>,
+    inference: Arc<InferenceRouter>,
+}
+
+impl PluginHost {
+    /// Loads every plugin in `MESHCLAW_PLUGIN_DIR` (default `./.meshclaw/plugins`).
+    pub fn load(memory: Arc<MemorySync>, inference: Arc<InferenceRouter>) -> anyhow::Result<Self> {
+        let dir = std::env::var("MESHCLAW_PLUGIN_DIR").unwrap_or_else(|_| "./.meshclaw/plugins".to_string());
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let mut host = PluginHost { engine, plugins: HashMap::new(), memory, inference };
+
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(host),
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            if let Err(e) = host.load_plugin(&path) {
+                eprintln!("Failed to load plugin {}: {}", path.display(), e);
+            }
+        }
+        Ok(host)
+    }
+
+    fn load_plugin(&mut self, manifest_path: &Path) -> anyhow::Result<()> {
+        let manifest: PluginManifest = serde_json::from_slice(&std::fs::read(manifest_path)?)?;
+        let module = Module::from_file(&self.engine, manifest_path.with_extension("wasm"))?;
+        println!("🦞 Plugin '{}' provides {:?}", manifest.name, manifest.capabilities);
+        let plugin = Arc::new(Plugin { manifest, module });
+        for cap in &plugin.manifest.capabilities {
+            self.plugins.insert(cap.clone(), plugin.clone());
+        }
+        Ok(())
+    }
+
+    pub fn provides(&self, capability: &str) -> bool {
+        self.plugins.contains_key(capability)
+    }
+
+    /// Capabilities with the name of the plugin serving each.
+    pub fn capabilities(&self) -> Vec<(String, String)> {
+        let mut caps: Vec<(String, String)> = self
+            .plugins
+            .iter()
+            .map(|(cap, p)| (cap.clone(), p.manifest.name.clone()))
+            .collect();
+        caps.sort();
+        caps
+    }
+
+    pub async fn call(&self, capability: &str, input: serde_json::Value) -> Result<serde_json::Value, PluginError> {
+        let plugin = self
+            .plugins
+            .get(capability)
+            .cloned()
+            .ok_or_else(|| PluginError::UnknownCapability(capability.to_string()))?;
+        let engine = self.engine.clone();
+        let state = HostState {
+            manifest: plugin.manifest.clone(),
+            limits: StoreLimitsBuilder::new().memory_size(plugin.manifest.max_memory_bytes).build(),
+            memory: self.memory.clone(),
+            inference: self.inference.clone(),
+            runtime: tokio::runtime::Handle::current(),
+        };
+        let capability = capability.to_string();
+
+        // Guests run synchronously on a blocking thread; host calls into
+        // inference block on the runtime from there.
+        tokio::task::spawn_blocking(move || run_plugin(&engine, &plugin.module, state, &capability, &input))
+            .await
+            .map_err(|e| PluginError::Trap(e.to_string()))?
+    }
+}
+
+fn guest_memory(caller: &mut Caller<'_, HostState>) -> anyhow::Result<wasmtime::Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|e| e.into_memory())
+        .ok_or_else(|| anyhow::anyhow!("plugin does not export memory"))
+}
+
+fn read_guest(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> anyhow::Result<Vec<u8>> {
+    let memory = guest_memory(caller)?;
+    // Wasm32 pointers and lengths are unsigned.
+    let (ptr, len) = (ptr as u32 as usize, len as u32 as usize);
+    if !in_bounds(ptr, len, memory.data_size(&*caller)) {
+        anyhow::bail!("guest buffer at {} of {} bytes is outside its memory", ptr, len);
+    }
+    let mut buf = vec![0u8; len];
+    memory.read(&*caller, ptr, &mut buf)?;
+    Ok(buf)
+}
+
+/// Whether `len` bytes at `ptr` fit in a guest memory of `size` bytes. Checked
+/// before allocating, since the guest chooses `len`.
+fn in_bounds(ptr: usize, len: usize, size: usize) -> bool {
+    ptr.checked_add(len).is_some_and(|end| end <= size)
+}
+
+fn read_guest_str(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> anyhow::Result<String> {
+    Ok(String::from_utf8(read_guest(caller, ptr, len)?)?)
+}
+
+/// Copies `bytes` into a guest buffer from `alloc` and returns `(ptr << 32) | len`.
+fn write_guest(caller: &mut Caller<'_, HostState>, bytes: &[u8]) -> anyhow::Result<i64> {
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(|e| e.into_func())
+        .ok_or_else(|| anyhow::anyhow!("plugin does not export alloc"))?
+        .typed::<i32, i32>(&*caller)?;
+    let ptr = alloc.call(&mut *caller, bytes.len() as i32)?;
+    let memory = guest_memory(caller)?;
+    memory.write(&mut *caller, ptr as usize, bytes)?;
+    Ok(((ptr as i64) << 32) | bytes.len() as i64)
+}
+
+fn link_host_api(linker: &mut Linker<HostState>) -> anyhow::Result<()> {
+    linker.func_wrap("meshclaw", "log", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<()> {
+        let msg = read_guest_str(&mut caller, ptr, len)?;
+        println!("🦞 Plugin '{}': {}", caller.data().manifest.name, msg);
+        Ok(())
+    })?;
+
+    linker.func_wrap("meshclaw", "memory_get", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<i64> {
+        let key = read_guest_str(&mut caller, ptr, len)?;
+        if !permitted(&caller.data().manifest.memory.read, &key) {
+            return Ok(-1);
+        }
+        match caller.data().memory.get_text(&key) {
+            Some(value) => write_guest(&mut caller, value.as_bytes()),
+            None => Ok(-1),
+        }
+    })?;
+
+    linker.func_wrap(
+        "meshclaw",
+        "memory_set",
+        |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32, val_ptr: i32, val_len: i32| -> anyhow::Result<i32> {
+            let key = read_guest_str(&mut caller, key_ptr, key_len)?;
+            if !permitted(&caller.data().manifest.memory.write, &key) {
+                return Ok(-1);
+            }
+            let value = read_guest_str(&mut caller, val_ptr, val_len)?;
+            match caller.data().memory.update_text(&key, &value) {
+                Ok(()) => Ok(0),
+                Err(_) => Ok(-1),
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "meshclaw",
+        "infer",
+        |mut caller: Caller<'_, HostState>, cap_ptr: i32, cap_len: i32, prompt_ptr: i32, prompt_len: i32| -> anyhow::Result<i64> {
+            let capability = read_guest_str(&mut caller, cap_ptr, cap_len)?;
+            if !caller.data().manifest.inference.contains(&capability) {
+                return Ok(-1);
+            }
+            let prompt = read_guest_str(&mut caller, prompt_ptr, prompt_len)?;
+            let state = caller.data();
+            let outcome = state.runtime.block_on(state.inference.generate(&capability, &prompt));
+            match outcome {
+                Ok(resp) => write_guest(&mut caller, resp.text.as_bytes()),
+                Err(e) => {
+                    eprintln!("🦞 Plugin '{}': inference failed: {}", caller.data().manifest.name, e);
+                    Ok(-1)
+                }
+            }
+        },
+    )?;
+    Ok(())
+}
+
+fn run_plugin(
+    engine: &Engine,
+    module: &Module,
+    state: HostState,
+    capability: &str,
+    input: &serde_json::Value,
+) -> Result<serde_json::Value, PluginError> {
+    let fuel = state.manifest.fuel;
+    let mut linker = Linker::new(engine);
+    link_host_api(&mut linker).map_err(|e| PluginError::Load(e.to_string()))?;
+
+    let mut store = Store::new(engine, state);
+    store.limiter(|s| &mut s.limits);
+    store.set_fuel(fuel).map_err(|e| PluginError::Load(e.to_string()))?;
+
+    let instance = linker
+        .instantiate(&mut store, module)
+        .map_err(|e| PluginError::Load(e.to_string()))?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| PluginError::Load("plugin does not export memory".to_string()))?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&mut store, "alloc")
+        .map_err(|e| PluginError::Load(e.to_string()))?;
+    let run = instance
+        .get_typed_func::<(i32, i32, i32, i32), i64>(&mut store, "run")
+        .map_err(|e| PluginError::Load(e.to_string()))?;
+
+    let write = |store: &mut Store<HostState>, bytes: &[u8]| -> Result<i32, PluginError> {
+        let ptr = alloc.call(&mut *store, bytes.len() as i32).map_err(|e| PluginError::Trap(e.to_string()))?;
+        memory
+            .write(&mut *store, ptr as usize, bytes)
+            .map_err(|e| PluginError::Trap(e.to_string()))?;
+        Ok(ptr)
+    };
+    let input_bytes = serde_json::to_vec(input).unwrap_or_default();
+    let cap_ptr = write(&mut store, capability.as_bytes())?;
+    let input_ptr = write(&mut store, &input_bytes)?;
+
+    let packed = run
+        .call(&mut store, (cap_ptr, capability.len() as i32, input_ptr, input_bytes.len() as i32))
+        .map_err(|e| PluginError::Trap(e.to_string()))?;
+    if packed < 0 {
+        return Err(PluginError::Trap(format!("run returned {}", packed)));
+    }
+
+    let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+    if !in_bounds(ptr, len, memory.data_size(&store)) {
+        return Err(PluginError::InvalidOutput(format!("output at {} of {} bytes is outside guest memory", ptr, len)));
+    }
+    let mut out = vec![0u8; len];
+    memory
+        .read(&store, ptr, &mut out)
+        .map_err(|e| PluginError::InvalidOutput(e.to_string()))?;
+    serde_json::from_slice(&out).map_err(|e| PluginError::InvalidOutput(e.to_string()))
+}
@@ -0,0 +1,182 @@
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::Tree;
+
+/// Writer entries kept per key; older ones are dropped.
+const MAX_WRITERS: usize = 20;
+/// Peer IDs are stored per document under this prefix and the big-endian yrs client ID.
+const CLIENT_PREFIX: &[u8] = b"client:";
+/// A key's writers are stored under this prefix and the key.
+const PROVENANCE_PREFIX: &[u8] = b"provenance:";
+
+/// A peer's signed claim to have written a memory update, broadcast with it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateEnvelope {
+    pub peer_id: String,
+    /// The yrs client ID the peer edits the document with.
+    pub client_id: u64,
+    pub timestamp: u64,
+    /// Protobuf-encoded public key of the peer.
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl UpdateEnvelope {
+    pub fn sign(keypair: &Keypair, doc_id: &str, client_id: u64, update: &[u8]) -> Option<Self> {
+        let mut envelope = UpdateEnvelope {
+            peer_id: PeerId::from(keypair.public()).to_string(),
+            client_id,
+            timestamp: now_secs(),
+            public_key: keypair.public().encode_protobuf(),
+            signature: Vec::new(),
+        };
+        envelope.signature = keypair.sign(&envelope.signed_bytes(doc_id, update)).ok()?;
+        Some(envelope)
+    }
+
+    /// Checks the signature over `update` and that the key belongs to `peer_id`.
+    pub fn verify(&self, doc_id: &str, update: &[u8]) -> bool {
+        let key = match PublicKey::try_decode_protobuf(&self.public_key) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+        PeerId::from(key.clone()).to_string() == self.peer_id && key.verify(&self.signed_bytes(doc_id, update), &self.signature)
+    }
+
+    fn signed_bytes(&self, doc_id: &str, update: &[u8]) -> Vec<u8> {
+        let digest: String = Sha256::digest(update).iter().map(|b| format!("{:02x}", b)).collect();
+        format!(
+            "meshclaw-update\n{}\n{}\n{}\n{}\n{}",
+            doc_id, self.peer_id, self.client_id, self.timestamp, digest
+        )
+        .into_bytes()
+    }
+}
+
+/// A peer's signed statement that it created or deleted a document. Nodes
+/// keep the latest of each kind per document and share them with peers, and
+/// the later of the two decides whether the document exists.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DocRecord {
+    pub doc_id: String,
+    pub deleted: bool,
+    /// Unix milliseconds.
+    pub at: u64,
+    pub peer_id: String,
+    /// Protobuf-encoded public key of the peer.
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl DocRecord {
+    pub fn sign(keypair: &Keypair, doc_id: &str, deleted: bool, at: u64) -> Option<Self> {
+        let mut record = DocRecord {
+            doc_id: doc_id.to_string(),
+            deleted,
+            at,
+            peer_id: PeerId::from(keypair.public()).to_string(),
+            public_key: keypair.public().encode_protobuf(),
+            signature: Vec::new(),
+        };
+        record.signature = keypair.sign(&record.signed_bytes()).ok()?;
+        Some(record)
+    }
+
+    /// Checks the signature and that the key belongs to `peer_id`.
+    pub fn verify(&self) -> bool {
+        let key = match PublicKey::try_decode_protobuf(&self.public_key) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+        PeerId::from(key.clone()).to_string() == self.peer_id && key.verify(&self.signed_bytes(), &self.signature)
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let action = if self.deleted { "delete" } else { "create" };
+        format!("meshclaw-doc\n{}\n{}\n{}\n{}", self.doc_id, action, self.at, self.peer_id).into_bytes()
+    }
+}
+
+/// One change to a key. Changes that added content name the yrs client that
+/// made them, resolved to a peer once its mapping is known; deletions carry
+/// no client, so they name the peer they arrived from. Changes that arrived
+/// in unsigned catch-up updates are marked unverified and never resolved,
+/// since nothing ties their blocks to the peers the clients belong to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Writer {
+    pub peer_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<u64>,
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unverified: bool,
+}
+
+/// Who changed a key and when, as served by the bridge.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyProvenance {
+    pub key: String,
+    pub last_writer: Option<String>,
+    pub updated_at: u64,
+    /// Oldest first.
+    pub writers: Vec<Writer>,
+}
+
+fn client_key(client_id: u64) -> Vec<u8> {
+    [CLIENT_PREFIX, &client_id.to_be_bytes()].concat()
+}
+
+fn provenance_key(key: &str) -> Vec<u8> {
+    [PROVENANCE_PREFIX, key.as_bytes()].concat()
+}
+
+/// Records that `peer_id` edits with `client_id`. The first claim stands, so
+/// a peer cannot take over changes another already signed for.
+pub fn map_client(tree: &Tree, client_id: u64, peer_id: &str) -> sled::Result<()> {
+    let _ = tree.compare_and_swap(client_key(client_id), None as Option<&[u8]>, Some(peer_id.as_bytes()))?;
+    Ok(())
+}
+
+pub fn peer_of(tree: &Tree, client_id: u64) -> Option<String> {
+    let bytes = tree.get(client_key(client_id)).ok()??;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Appends writers to a key's history.
+pub fn record(tree: &Tree, key: &str, writers: &[Writer]) -> anyhow::Result<()> {
+    let mut history = load(tree, key);
+    history.extend_from_slice(writers);
+    if history.len() > MAX_WRITERS {
+        history.drain(..history.len() - MAX_WRITERS);
+    }
+    tree.insert(provenance_key(key), serde_json::to_vec(&history)?)?;
+    Ok(())
+}
+
+/// A key's history with client IDs resolved to peers where known.
+pub fn read(tree: &Tree, key: &str) -> Option<KeyProvenance> {
+    let mut writers = load(tree, key);
+    for writer in writers.iter_mut().filter(|w| w.peer_id.is_none() && !w.unverified) {
+        writer.peer_id = writer.client_id.and_then(|client_id| peer_of(tree, client_id));
+    }
+    let (last_writer, updated_at) = writers.last().map(|w| (w.peer_id.clone(), w.timestamp))?;
+    Some(KeyProvenance { key: key.to_string(), last_writer, updated_at, writers })
+}
+
+fn load(tree: &Tree, key: &str) -> Vec<Writer> {
+    tree.get(provenance_key(key))
+        .ok()
+        .flatten()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+}
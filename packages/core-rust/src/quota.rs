@@ -0,0 +1,180 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const HOUR_SECS: u64 = 3600;
+/// Hourly buckets kept per requester, enough for the daily window.
+const BUCKETS: u64 = 24;
+
+/// Work done for one requester.
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub tasks: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub compute_ms: u64,
+}
+
+impl Usage {
+    pub fn tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    fn add(&mut self, other: &Usage) {
+        self.tasks += other.tasks;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.compute_ms += other.compute_ms;
+    }
+}
+
+/// Per-requester limits; `None` means unlimited.
+#[derive(Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaLimits {
+    pub tasks_per_hour: Option<u64>,
+    pub tasks_per_day: Option<u64>,
+    pub tokens_per_hour: Option<u64>,
+    pub tokens_per_day: Option<u64>,
+}
+
+impl QuotaLimits {
+    /// Read from `MESHCLAW_QUOTA_TASKS_PER_HOUR`, `MESHCLAW_QUOTA_TASKS_PER_DAY`,
+    /// `MESHCLAW_QUOTA_TOKENS_PER_HOUR` and `MESHCLAW_QUOTA_TOKENS_PER_DAY`.
+    pub fn from_env() -> Self {
+        let limit = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok());
+        QuotaLimits {
+            tasks_per_hour: limit("MESHCLAW_QUOTA_TASKS_PER_HOUR"),
+            tasks_per_day: limit("MESHCLAW_QUOTA_TASKS_PER_DAY"),
+            tokens_per_hour: limit("MESHCLAW_QUOTA_TOKENS_PER_HOUR"),
+            tokens_per_day: limit("MESHCLAW_QUOTA_TOKENS_PER_DAY"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum QuotaError {
+    Tasks { window: &'static str, limit: u64 },
+    Tokens { window: &'static str, limit: u64 },
+}
+
+impl QuotaError {
+    pub fn kind(&self) -> &'static str {
+        "quota-exceeded"
+    }
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaError::Tasks { window, limit } => write!(f, "requester reached its quota of {} tasks per {}", limit, window),
+            QuotaError::Tokens { window, limit } => write!(f, "requester reached its quota of {} tokens per {}", limit, window),
+        }
+    }
+}
+
+impl std::error::Error for QuotaError {}
+
+/// Usage per requester in hourly buckets, checked against `QuotaLimits` before
+/// a delegated task is accepted. Hourly limits apply to the current clock hour,
+/// daily ones to the last 24 buckets. Our own tasks are counted but never refused.
+/// Usage is kept in memory only, so it starts over when the node restarts.
+pub struct QuotaTracker {
+    local_id: String,
+    limits: QuotaLimits,
+    buckets: Mutex<HashMap<String, VecDeque<(u64, Usage)>>>,
+}
+
+impl QuotaTracker {
+    pub fn new(local_id: String, limits: QuotaLimits) -> Self {
+        QuotaTracker { local_id, limits, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn from_env(local_id: String) -> Self {
+        QuotaTracker::new(local_id, QuotaLimits::from_env())
+    }
+
+    /// Whether another task from `requester` would be accepted.
+    pub fn check(&self, requester: &str) -> Result<(), QuotaError> {
+        if requester == self.local_id {
+            return Ok(());
+        }
+        let (hour, day) = self.usage(requester);
+        let checks = [
+            (hour.tasks, self.limits.tasks_per_hour, "hour", false),
+            (day.tasks, self.limits.tasks_per_day, "day", false),
+            (hour.tokens(), self.limits.tokens_per_hour, "hour", true),
+            (day.tokens(), self.limits.tokens_per_day, "day", true),
+        ];
+        for (used, limit, window, tokens) in checks {
+            match limit {
+                Some(limit) if used >= limit && tokens => return Err(QuotaError::Tokens { window, limit }),
+                Some(limit) if used >= limit => return Err(QuotaError::Tasks { window, limit }),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the quota and counts the task against it.
+    pub fn admit(&self, requester: &str) -> Result<(), QuotaError> {
+        self.check(requester)?;
+        self.record(requester, Usage { tasks: 1, ..Usage::default() });
+        Ok(())
+    }
+
+    /// Adds tokens and compute time spent on a finished task.
+    pub fn record_work(&self, requester: &str, prompt_tokens: u64, completion_tokens: u64, compute: Duration) {
+        let usage = Usage { tasks: 0, prompt_tokens, completion_tokens, compute_ms: compute.as_millis() as u64 };
+        self.record(requester, usage);
+    }
+
+    fn record(&self, requester: &str, usage: Usage) {
+        let hour = current_hour();
+        let mut buckets = self.buckets.lock().unwrap();
+        let entries = buckets.entry(requester.to_string()).or_default();
+        while entries.front().is_some_and(|(h, _)| *h + BUCKETS <= hour) {
+            entries.pop_front();
+        }
+        match entries.back_mut() {
+            Some((h, bucket)) if *h == hour => bucket.add(&usage),
+            _ => entries.push_back((hour, usage)),
+        }
+    }
+
+    /// Usage by `requester` in the current hour and the last 24 hours.
+    pub fn usage(&self, requester: &str) -> (Usage, Usage) {
+        let hour = current_hour();
+        let buckets = self.buckets.lock().unwrap();
+        let (mut last_hour, mut last_day) = (Usage::default(), Usage::default());
+        for (h, bucket) in buckets.get(requester).into_iter().flatten() {
+            if *h + BUCKETS > hour {
+                last_day.add(bucket);
+            }
+            if *h == hour {
+                last_hour.add(bucket);
+            }
+        }
+        (last_hour, last_day)
+    }
+
+    /// Limits and per-requester usage, as served by the bridge.
+    pub fn report(&self) -> serde_json::Value {
+        let requesters: Vec<String> = self.buckets.lock().unwrap().keys().cloned().collect();
+        let mut usage = serde_json::Map::new();
+        for requester in requesters {
+            let (hour, day) = self.usage(&requester);
+            if day.tasks > 0 || day.compute_ms > 0 || day.tokens() > 0 {
+                usage.insert(requester, serde_json::json!({ "hour": hour, "day": day }));
+            }
+        }
+        serde_json::json!({ "limits": self.limits, "requesters": usage })
+    }
+}
+
+fn current_hour() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() / HOUR_SECS
+}
@@ -0,0 +1,679 @@
+use libp2p::PeerId;
+use meshclaw_core::auction::{Auction, Bid, DEFAULT_BID_WINDOW};
+use meshclaw_core::cache::{Provenance, ResultCache};
+use meshclaw_core::graph::GraphRun;
+use meshclaw_core::inference::{GenerationParams, InferenceRouter, DEFAULT_CAPABILITY};
+use meshclaw_core::ledger::{Ledger, Receipt};
+use meshclaw_core::scheduler::{Preferences, Scheduler};
+use meshclaw_core::stream::TaskStream;
+use meshclaw_core::sync::{MemorySync, SyncMessage};
+use meshclaw_core::voting::{ComparatorRegistry, Redundancy, VoteRound};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a dispatched task may go without a chunk, status or result
+/// before it fails; longer than the default approval timeout, so held tasks
+/// are not abandoned while an operator decides.
+pub const DEFAULT_TASK_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Final state of a delegated task, assembled from its chunks and result marker.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub status: String,
+    pub model: Option<String>,
+    pub result: Option<String>,
+    pub output: Option<serde_json::Value>,
+    pub error: Option<serde_json::Value>,
+    pub receipt: Option<Receipt>,
+    pub provenance: Option<Provenance>,
+}
+
+impl Completion {
+    fn failed(kind: &str, message: &str) -> Self {
+        Completion {
+            status: "failed".to_string(),
+            model: None,
+            result: None,
+            output: None,
+            error: Some(serde_json::json!({ "kind": kind, "message": message })),
+            receipt: None,
+            provenance: None,
+        }
+    }
+
+    fn is_success(&self) -> bool {
+        self.status == "completed"
+    }
+
+    /// The answer compared between replicas: structured output if any, else the text.
+    fn answer(&self) -> Option<serde_json::Value> {
+        if !self.is_success() {
+            return None;
+        }
+        self.output.clone().or_else(|| self.result.clone().map(serde_json::Value::String))
+    }
+}
+
+struct ActiveGraph {
+    run: GraphRun,
+    requester_id: String,
+}
+
+/// Bookkeeping for tasks delegated from our gateway: provider selection,
+/// bidding, stream reassembly, redundant execution, task graphs, the result
+/// cache and reporting back to the gateway.
+pub struct Requester {
+    pub scheduler: Scheduler,
+    ledger: Arc<Ledger>,
+    /// When each in-flight task was handed to its provider.
+    started: HashMap<String, Instant>,
+    /// When we last heard from the provider of each in-flight task.
+    last_heard: HashMap<String, Instant>,
+    idle_timeout: Duration,
+    /// Receipt and provenance of results still waiting for late chunks.
+    finals: HashMap<String, (Option<Receipt>, Option<Provenance>)>,
+    streams: HashMap<String, TaskStream>,
+    rounds: HashMap<String, VoteRound>,
+    /// Replica task ID -> (original task ID, provider).
+    replica_of: HashMap<String, (String, PeerId)>,
+    comparators: ComparatorRegistry,
+    graphs: HashMap<String, ActiveGraph>,
+    /// Step task ID -> (graph ID, step ID).
+    step_of: HashMap<String, (String, String)>,
+    auctions: HashMap<String, Auction>,
+    /// Receives task IDs whose bidding window closed.
+    auction_tx: tokio::sync::mpsc::Sender<String>,
+    cache: Option<Arc<ResultCache>>,
+    inference: Arc<InferenceRouter>,
+    memory: Arc<MemorySync>,
+    /// Feeds graph steps back into the bridge path, so they are routed like
+    /// any other `Delegate`.
+    delegate_tx: tokio::sync::mpsc::Sender<SyncMessage>,
+    gateway_tx: tokio::sync::broadcast::Sender<serde_json::Value>,
+}
+
+impl Requester {
+    /// Dispatched tasks fail after `MESHCLAW_TASK_IDLE_TIMEOUT_SECS` (default
+    /// 600) without word from their provider.
+    pub fn new(
+        scheduler: Scheduler,
+        ledger: Arc<Ledger>,
+        cache: Option<Arc<ResultCache>>,
+        inference: Arc<InferenceRouter>,
+        memory: Arc<MemorySync>,
+        auction_tx: tokio::sync::mpsc::Sender<String>,
+        delegate_tx: tokio::sync::mpsc::Sender<SyncMessage>,
+        gateway_tx: tokio::sync::broadcast::Sender<serde_json::Value>,
+    ) -> Self {
+        Requester {
+            scheduler,
+            ledger,
+            started: HashMap::new(),
+            last_heard: HashMap::new(),
+            idle_timeout: std::env::var("MESHCLAW_TASK_IDLE_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TASK_IDLE_TIMEOUT),
+            finals: HashMap::new(),
+            streams: HashMap::new(),
+            rounds: HashMap::new(),
+            replica_of: HashMap::new(),
+            comparators: ComparatorRegistry::new(),
+            graphs: HashMap::new(),
+            step_of: HashMap::new(),
+            auctions: HashMap::new(),
+            auction_tx,
+            cache,
+            inference,
+            memory,
+            delegate_tx,
+            gateway_tx,
+        }
+    }
+
+    /// Starts tracking a task we requested; `assignee` is known for direct assignments.
+    pub fn track(&mut self, task_id: &str, assignee: Option<PeerId>) {
+        self.streams.insert(task_id.to_string(), TaskStream::new());
+        if let Some(peer) = assignee {
+            self.record_dispatch(task_id, peer);
+        }
+    }
+
+    /// Assigns a capability-addressed `Delegate` to providers found in the DHT.
+    /// Returns the messages to send: one, or one per replica when the payload
+    /// asks for redundant execution.
+    pub fn dispatch(&mut self, msg: SyncMessage, providers: &HashSet<PeerId>) -> Vec<SyncMessage> {
+        let (task_id, capability, payload) = match &msg {
+            SyncMessage::Delegate { task_id, capability, payload, .. } => (task_id.clone(), capability.clone(), payload.clone()),
+            _ => return Vec::new(),
+        };
+        let prefs = Preferences::from_payload(&payload);
+        let redundancy = Redundancy::from_payload(&payload);
+        let wanted = redundancy.as_ref().map(|r| r.replicas).unwrap_or(1);
+        let selected = self.scheduler.select_many(providers, &prefs, wanted);
+
+        let mut list: Vec<String> = providers.iter().map(|p| p.to_string()).collect();
+        list.sort();
+        let _ = self.gateway_tx.send(serde_json::json!({
+            "type": "event",
+            "method": "mesh:providers",
+            "params": {
+                "taskId": task_id,
+                "capability": capability,
+                "providers": list,
+                "selected": selected.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+                "strategy": self.scheduler.strategy()
+            }
+        }));
+        self.assign_selected(msg, selected)
+    }
+
+    /// Starts contract-net bidding when the payload asks for it, returning the
+    /// call for proposals to broadcast. Bids are awarded by `close_auction`
+    /// once the window passes.
+    pub fn start_auction(&mut self, msg: &SyncMessage) -> Option<SyncMessage> {
+        let (task_id, requester_id, capability, payload) = match msg {
+            SyncMessage::Delegate { task_id, requester_id, capability, payload, .. } => (task_id, requester_id, capability, payload),
+            _ => return None,
+        };
+        let prefs = Preferences::from_payload(payload);
+        if !prefs.auction {
+            return None;
+        }
+        let window = prefs.bid_window_ms.map(Duration::from_millis).unwrap_or(DEFAULT_BID_WINDOW);
+        println!("Calling for proposals on task {} ({}ms)", task_id, window.as_millis());
+        self.auctions.insert(task_id.clone(), Auction::new(msg.clone()));
+
+        let auction_tx = self.auction_tx.clone();
+        let closing = task_id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let _ = auction_tx.send(closing).await;
+        });
+
+        Some(SyncMessage::CallForProposals {
+            task_id: task_id.clone(),
+            requester_id: requester_id.clone(),
+            capability: capability.clone().unwrap_or_else(|| DEFAULT_CAPABILITY.to_string()),
+            deadline_ms: window.as_millis() as u64,
+            timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+        })
+    }
+
+    /// Records a bid; bids for unknown or closed auctions are ignored.
+    pub fn record_bid(&mut self, task_id: &str, bid: Bid) {
+        let peer = match bid.bidder_id.parse::<PeerId>() {
+            Ok(peer) => peer,
+            Err(_) => return,
+        };
+        if let Some(auction) = self.auctions.get_mut(task_id) {
+            auction.record(peer, bid);
+        }
+    }
+
+    /// Awards the task to the best bids received before the deadline.
+    pub fn close_auction(&mut self, task_id: &str) -> Vec<SyncMessage> {
+        let auction = match self.auctions.remove(task_id) {
+            Some(auction) => auction,
+            None => return Vec::new(),
+        };
+        let payload = match auction.msg() {
+            SyncMessage::Delegate { payload, .. } => payload.clone(),
+            _ => return Vec::new(),
+        };
+        let prefs = Preferences::from_payload(&payload);
+        let wanted = Redundancy::from_payload(&payload).map(|r| r.replicas).unwrap_or(1);
+        let scheduler = &self.scheduler;
+        let mut selected = auction.ranked(&prefs, |peer| scheduler.stats(peer));
+        selected.truncate(wanted);
+
+        let bids: Vec<&Bid> = auction.bids().iter().map(|(_, bid)| bid).collect();
+        let _ = self.gateway_tx.send(serde_json::json!({
+            "type": "event",
+            "method": "mesh:auction",
+            "params": {
+                "taskId": task_id,
+                "bids": bids,
+                "selected": selected.iter().map(|p| p.to_string()).collect::<Vec<_>>()
+            }
+        }));
+        self.assign_selected(auction.into_msg(), selected)
+    }
+
+    /// Assigns a `Delegate` to the chosen providers: one, or one per replica
+    /// when the payload asks for redundant execution.
+    fn assign_selected(&mut self, msg: SyncMessage, selected: Vec<PeerId>) -> Vec<SyncMessage> {
+        let (task_id, capability, payload) = match &msg {
+            SyncMessage::Delegate { task_id, capability, payload, .. } => (task_id.clone(), capability.clone(), payload.clone()),
+            _ => return Vec::new(),
+        };
+        let redundancy = Redundancy::from_payload(&payload);
+
+        if selected.is_empty() {
+            eprintln!("No provider found for task {task_id}");
+            self.streams.remove(&task_id);
+            self.deliver(&task_id, Completion::failed("no-provider", &format!("no provider found for {:?}", capability)), None);
+            return Vec::new();
+        }
+
+        let redundancy = match redundancy {
+            Some(r) => r,
+            None => {
+                let peer = selected[0];
+                self.record_dispatch(&task_id, peer);
+                return vec![assign(msg, &task_id, peer)];
+            }
+        };
+
+        let comparator = match self.comparators.get(&redundancy.comparator) {
+            Some(c) => c,
+            None => {
+                self.streams.remove(&task_id);
+                self.deliver(&task_id, Completion::failed("unknown-comparator", &format!("no comparator named '{}'", redundancy.comparator)), None);
+                return Vec::new();
+            }
+        };
+        println!("Dispatching task {} to {} replicas", task_id, selected.len());
+
+        // Replicas are streamed individually; only the voted answer reaches the gateway.
+        self.streams.remove(&task_id);
+        self.rounds.insert(
+            task_id.clone(),
+            VoteRound::new(redundancy.vote, comparator, selected.clone(), Duration::from_secs(redundancy.timeout_secs)),
+        );
+        selected
+            .into_iter()
+            .enumerate()
+            .map(|(i, peer)| {
+                let replica_id = format!("{}#{}", task_id, i);
+                self.streams.insert(replica_id.clone(), TaskStream::new());
+                self.record_dispatch(&replica_id, peer);
+                self.replica_of.insert(replica_id.clone(), (task_id.clone(), peer));
+                assign(msg.clone(), &replica_id, peer)
+            })
+            .collect()
+    }
+
+    /// Feeds chunk and completion messages for tasks we requested into their
+    /// `TaskStream`, forwarding in-order `mesh:agent:chunk` events, provider
+    /// `mesh:agent:status` updates and the final `mesh:agent:result` to the
+    /// gateway. `source` is the peer that published the message; for tasks
+    /// with a known provider, messages from anyone else are dropped. Returns
+    /// false for tasks we did not request.
+    pub fn handle(&mut self, msg: &SyncMessage, source: Option<PeerId>) -> bool {
+        let task_id = match msg {
+            SyncMessage::TaskChunk { task_id, .. }
+            | SyncMessage::TaskResult { task_id, .. }
+            | SyncMessage::TaskStatus { task_id, .. } => task_id,
+            _ => return false,
+        };
+        if !self.streams.contains_key(task_id) {
+            return false;
+        }
+        let replica_peer = self.replica_of.get(task_id).map(|(_, peer)| *peer);
+        if let Some(expected) = replica_peer.or_else(|| self.scheduler.assignee(task_id)) {
+            if source != Some(expected) {
+                eprintln!("Ignoring message for task {} from {:?}: it was assigned to {}", task_id, source, expected);
+                return true;
+            }
+        }
+        self.last_heard.insert(task_id.clone(), Instant::now());
+        // A replica's answer only counts with a receipt signed by its provider,
+        // so votes cannot be cast under another node's name.
+        if let (Some(peer), SyncMessage::TaskResult { status, receipt, .. }) = (replica_peer, msg) {
+            let signed = receipt
+                .as_ref()
+                .is_some_and(|r| r.verify() && r.provider_id == peer.to_string() && r.task_id == *task_id);
+            if status == "completed" && !signed {
+                self.streams.remove(task_id);
+                self.complete(task_id, Completion::failed("invalid-receipt", "replica result carried no valid receipt from its provider"));
+                return true;
+            }
+        }
+        // Replica and graph-step chunks are not forwarded; only their final answer counts.
+        let quiet = replica_peer.is_some() || self.step_of.contains_key(task_id);
+        let stream = match self.streams.get_mut(task_id) {
+            Some(stream) => stream,
+            None => return false,
+        };
+
+        match msg {
+            SyncMessage::TaskChunk { seq, delta, .. } => {
+                for (seq, delta) in stream.push(*seq, delta.clone()) {
+                    if !quiet {
+                        let _ = self.gateway_tx.send(serde_json::json!({
+                            "type": "event",
+                            "method": "mesh:agent:chunk",
+                            "params": { "taskId": task_id, "seq": seq, "delta": delta }
+                        }));
+                    }
+                }
+            },
+            SyncMessage::TaskResult { status, chunks, model, output, error, receipt, provenance, .. } => {
+                if status != "completed" {
+                    self.streams.remove(task_id);
+                    let completion = Completion {
+                        status: status.clone(),
+                        model: None,
+                        result: None,
+                        output: None,
+                        error: error.clone(),
+                        receipt: receipt.clone(),
+                        provenance: None,
+                    };
+                    self.complete(task_id, completion);
+                    return true;
+                }
+                stream.finish(*chunks, model.clone(), output.clone());
+                self.finals.insert(task_id.clone(), (receipt.clone(), provenance.clone()));
+            },
+            SyncMessage::TaskStatus { status, .. } => {
+                if !quiet {
+                    let _ = self.gateway_tx.send(serde_json::json!({
+                        "type": "event",
+                        "method": "mesh:agent:status",
+                        "params": { "taskId": task_id, "status": status }
+                    }));
+                }
+                return true;
+            },
+            _ => {}
+        }
+
+        if stream.is_complete() {
+            let (receipt, provenance) = self.finals.remove(task_id).unwrap_or_default();
+            let completion = Completion {
+                status: "completed".to_string(),
+                model: stream.model().map(|m| m.to_string()),
+                result: Some(stream.text().to_string()),
+                output: stream.output().cloned(),
+                error: None,
+                receipt,
+                provenance,
+            };
+            self.streams.remove(task_id);
+            self.complete(task_id, completion);
+        }
+        true
+    }
+
+    /// Closes vote rounds whose replicas did not all answer before the deadline,
+    /// and fails dispatched tasks whose provider has gone quiet, so a provider
+    /// that stops mid-stream does not leave the task open forever.
+    pub fn sweep(&mut self, now: Instant) {
+        let expired: Vec<String> = self
+            .rounds
+            .iter()
+            .filter(|(_, round)| round.is_expired(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for task_id in expired {
+            self.finish_round(&task_id);
+        }
+
+        let idle: Vec<String> = self
+            .last_heard
+            .iter()
+            .filter(|(_, heard)| now.saturating_duration_since(**heard) >= self.idle_timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for task_id in idle {
+            self.last_heard.remove(&task_id);
+            self.finals.remove(&task_id);
+            if self.streams.remove(&task_id).is_some() {
+                eprintln!("Task {} timed out waiting for its provider", task_id);
+                let message = format!("no word from the provider for {}s", self.idle_timeout.as_secs());
+                self.complete(&task_id, Completion::failed("timeout", &message));
+            }
+        }
+    }
+
+    fn record_dispatch(&mut self, task_id: &str, peer: PeerId) {
+        self.scheduler.record_dispatch(task_id, peer);
+        self.started.insert(task_id.to_string(), Instant::now());
+        self.last_heard.insert(task_id.to_string(), Instant::now());
+    }
+
+    fn complete(&mut self, task_id: &str, completion: Completion) {
+        let latency = self.started.remove(task_id).map(|t| t.elapsed()).unwrap_or_default();
+        self.last_heard.remove(task_id);
+        if let Some(peer) = self.scheduler.record_outcome(task_id, completion.is_success()) {
+            self.ledger.record_delegated(&peer.to_string(), task_id, completion.is_success(), latency, completion.receipt.clone());
+        }
+
+        if let Some((parent, peer)) = self.replica_of.remove(task_id) {
+            if let Some(round) = self.rounds.get_mut(&parent) {
+                round.record(peer, completion.answer());
+                if round.is_complete() {
+                    self.finish_round(&parent);
+                }
+            }
+            return;
+        }
+        self.deliver(task_id, completion, None);
+    }
+
+    fn finish_round(&mut self, task_id: &str) {
+        let round = match self.rounds.remove(task_id) {
+            Some(round) => round,
+            None => return,
+        };
+        // Drop replicas that never answered.
+        let prefix = format!("{}#", task_id);
+        self.replica_of.retain(|id, _| !id.starts_with(&prefix));
+        self.streams.retain(|id, _| !id.starts_with(&prefix));
+        self.started.retain(|id, _| !id.starts_with(&prefix));
+        self.last_heard.retain(|id, _| !id.starts_with(&prefix));
+        self.finals.retain(|id, _| !id.starts_with(&prefix));
+
+        let outcome = round.decide();
+        if outcome.accepted.is_some() {
+            for peer in &outcome.dissenting {
+                println!("Provider {peer} disagreed with the accepted answer for task {task_id}");
+                self.scheduler.record_disagreement(*peer);
+            }
+        }
+
+        let votes = serde_json::json!({
+            "agreeing": outcome.agreeing.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            "dissenting": outcome.dissenting.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            "failed": outcome.failed.iter().map(|p| p.to_string()).collect::<Vec<_>>()
+        });
+        let completion = match outcome.accepted {
+            Some(serde_json::Value::String(text)) => Completion {
+                status: "completed".to_string(),
+                model: None,
+                result: Some(text),
+                output: None,
+                error: None,
+                receipt: None,
+                provenance: None,
+            },
+            Some(output) => Completion {
+                status: "completed".to_string(),
+                model: None,
+                result: None,
+                output: Some(output),
+                error: None,
+                receipt: None,
+                provenance: None,
+            },
+            None => Completion::failed("no-consensus", "replicas did not agree on an answer"),
+        };
+        self.deliver(task_id, completion, Some(votes));
+    }
+
+    /// Starts a task graph, dispatching its initial steps.
+    pub fn start_graph(&mut self, graph_id: String, requester_id: String, steps: Vec<meshclaw_core::graph::GraphStep>) {
+        match GraphRun::new(steps) {
+            Ok(run) => {
+                println!("Starting task graph {}", graph_id);
+                self.graphs.insert(graph_id.clone(), ActiveGraph { run, requester_id });
+                self.advance_graph(&graph_id);
+            }
+            Err(e) => {
+                eprintln!("Rejected task graph {}: {}", graph_id, e);
+                self.emit_result(&graph_id, &Completion::failed(e.kind(), &e.to_string()), None);
+            }
+        }
+    }
+
+    /// Dispatches steps that became ready and reports the graph once it is done.
+    fn advance_graph(&mut self, graph_id: &str) {
+        let graph = match self.graphs.get_mut(graph_id) {
+            Some(graph) => graph,
+            None => return,
+        };
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        for step in graph.run.take_ready() {
+            let task_id = format!("{}/{}", graph_id, step.id);
+            let capability = step.capability.unwrap_or_else(|| DEFAULT_CAPABILITY.to_string());
+            self.step_of.insert(task_id.clone(), (graph_id.to_string(), step.id));
+            let msg = SyncMessage::Delegate {
+                task_id,
+                task_desc: step.task_desc,
+                requester_id: graph.requester_id.clone(),
+                assignee_id: format!("cap:{}", capability),
+                payload: step.payload,
+                timestamp,
+                capability: Some(capability),
+            };
+            let delegate_tx = self.delegate_tx.clone();
+            tokio::spawn(async move {
+                let _ = delegate_tx.send(msg).await;
+            });
+        }
+        if !graph.run.is_finished() {
+            return;
+        }
+
+        let graph = self.graphs.remove(graph_id).unwrap();
+        let mut completion = if graph.run.is_success() {
+            Completion {
+                status: "completed".to_string(),
+                model: None,
+                result: None,
+                output: None,
+                error: None,
+                receipt: None,
+                provenance: None,
+            }
+        } else {
+            Completion::failed("step-failed", "one or more steps failed; see output for partial results")
+        };
+        completion.output = Some(graph.run.report());
+        self.emit_result(graph_id, &completion, None);
+    }
+
+    fn step_finished(&mut self, graph_id: &str, step_id: &str, completion: Completion) {
+        let graph = match self.graphs.get_mut(graph_id) {
+            Some(graph) => graph,
+            None => return,
+        };
+        let _ = self.gateway_tx.send(serde_json::json!({
+            "type": "event",
+            "method": "mesh:graph:step",
+            "params": {
+                "graphId": graph_id,
+                "stepId": step_id,
+                "status": completion.status,
+                "output": completion.answer(),
+                "error": completion.error
+            }
+        }));
+        match completion.answer() {
+            Some(output) => graph.run.complete(step_id, output),
+            None => graph.run.fail(step_id, completion.error.unwrap_or_default()),
+        }
+        self.advance_graph(graph_id);
+    }
+
+    /// Cache key of a capability-addressed LLM `Delegate`, when the result
+    /// cache is enabled and the request is deterministic. The key depends on
+    /// the model and options the capability resolves to, so it is only known
+    /// for capabilities this node routes itself.
+    pub fn cache_key(&self, msg: &SyncMessage) -> Option<String> {
+        self.cache.as_ref()?;
+        let (task_desc, capability, payload) = match msg {
+            SyncMessage::Delegate { task_desc, capability, payload, .. } => (task_desc, capability, payload),
+            _ => return None,
+        };
+        let capability = capability.as_deref().unwrap_or(DEFAULT_CAPABILITY);
+        if !capability.starts_with("llm:") {
+            return None;
+        }
+        // Invalid payloads are left for the provider to reject.
+        let params = GenerationParams::from_payload(payload).ok()?;
+        let prompt = params.expand_prompt(task_desc, &self.memory).ok()?;
+        let route = self.inference.resolve(capability, &params).ok()?;
+        ResultCache::key(&route, &prompt, &params)
+    }
+
+    /// Answers a task from the local cache; returns false on a miss.
+    pub fn answer_from_cache(&mut self, task_id: &str, key: &str) -> bool {
+        let hit = match self.cache.as_ref().and_then(|cache| cache.get(key)) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        println!("Answering task {} from the local result cache", task_id);
+        self.streams.remove(task_id);
+        let completion = Completion {
+            status: "completed".to_string(),
+            model: hit.model,
+            result: Some(hit.text),
+            output: hit.output,
+            error: None,
+            receipt: None,
+            provenance: Some(hit.provenance),
+        };
+        self.deliver(task_id, completion, None);
+        true
+    }
+
+    /// Hands a finished task to the graph it belongs to, or to the gateway.
+    fn deliver(&mut self, task_id: &str, completion: Completion, votes: Option<serde_json::Value>) {
+        if let Some((graph_id, step_id)) = self.step_of.remove(task_id) {
+            self.step_finished(&graph_id, &step_id, completion);
+            return;
+        }
+        self.emit_result(task_id, &completion, votes);
+    }
+
+    fn emit_result(&self, task_id: &str, completion: &Completion, votes: Option<serde_json::Value>) {
+        let mut params = serde_json::json!({
+            "taskId": task_id,
+            "status": completion.status
+        });
+        if completion.is_success() {
+            params["model"] = serde_json::json!(completion.model);
+            params["result"] = serde_json::json!(completion.result.as_deref().unwrap_or_default());
+            params["output"] = serde_json::json!(completion.output);
+        } else {
+            params["error"] = serde_json::json!(completion.error);
+            if completion.output.is_some() {
+                params["output"] = serde_json::json!(completion.output);
+            }
+        }
+        if let Some(votes) = votes {
+            params["votes"] = votes;
+        }
+        if let Some(provenance) = &completion.provenance {
+            params["provenance"] = serde_json::json!(provenance);
+        }
+        let _ = self.gateway_tx.send(serde_json::json!({
+            "type": "event",
+            "method": "mesh:agent:result",
+            "params": params
+        }));
+    }
+}
+
+fn assign(mut msg: SyncMessage, id: &str, peer: PeerId) -> SyncMessage {
+    if let SyncMessage::Delegate { ref mut task_id, ref mut assignee_id, .. } = msg {
+        *task_id = id.to_string();
+        *assignee_id = peer.to_string();
+    }
+    msg
+}
@@ -0,0 +1,217 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Weighs success rate against queue depth and latency.
+    #[default]
+    Balanced,
+    RoundRobin,
+    LeastLoaded,
+    LowestLatency,
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "balanced" => Ok(Strategy::Balanced),
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "least-loaded" => Ok(Strategy::LeastLoaded),
+            "lowest-latency" => Ok(Strategy::LowestLatency),
+            other => Err(anyhow::anyhow!("unknown scheduling strategy '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PeerStats {
+    pub queue_depth: Option<u32>,
+    pub rtt: Option<Duration>,
+    pub successes: u32,
+    pub failures: u32,
+    /// Redundant executions where this peer's answer was outvoted.
+    pub disagreements: u32,
+}
+
+impl PeerStats {
+    /// Laplace-smoothed, so untried peers start at 0.5 rather than 0 or 1.
+    /// Outvoted answers count against the peer like failures.
+    pub fn success_rate(&self) -> f64 {
+        let bad = self.failures as f64 + self.disagreements as f64;
+        (self.successes as f64 + 1.0) / (self.successes as f64 + bad + 2.0)
+    }
+}
+
+/// Requester-side scheduling hints, read from `payload.scheduling` of a `Delegate`.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Preferences {
+    #[serde(default)]
+    pub strategy: Option<Strategy>,
+    #[serde(default)]
+    pub prefer: Vec<String>,
+    #[serde(default)]
+    pub avoid: Vec<String>,
+    #[serde(default)]
+    pub max_latency_ms: Option<u64>,
+    /// Award the task by contract-net bidding instead of DHT lookup.
+    #[serde(default)]
+    pub auction: bool,
+    #[serde(default)]
+    pub bid_window_ms: Option<u64>,
+}
+
+impl Preferences {
+    pub fn from_payload(payload: &serde_json::Value) -> Self {
+        payload
+            .get("scheduling")
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Picks a provider among the peers advertising a capability.
+pub struct Scheduler {
+    strategy: Strategy,
+    stats: HashMap<PeerId, PeerStats>,
+    in_flight: HashMap<String, PeerId>,
+    cursor: usize,
+}
+
+impl Scheduler {
+    pub fn new(strategy: Strategy) -> Self {
+        Scheduler { strategy, stats: HashMap::new(), in_flight: HashMap::new(), cursor: 0 }
+    }
+
+    /// Uses `MESHCLAW_SCHEDULER_STRATEGY` when set, otherwise `balanced`.
+    pub fn from_env() -> Self {
+        let strategy = match std::env::var("MESHCLAW_SCHEDULER_STRATEGY") {
+            Ok(s) => s.parse().unwrap_or_else(|e| {
+                eprintln!("{}; falling back to balanced", e);
+                Strategy::Balanced
+            }),
+            Err(_) => Strategy::Balanced,
+        };
+        Scheduler::new(strategy)
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn stats(&self, peer: &PeerId) -> Option<&PeerStats> {
+        self.stats.get(peer)
+    }
+
+    pub fn record_rtt(&mut self, peer: PeerId, rtt: Duration) {
+        self.stats.entry(peer).or_default().rtt = Some(rtt);
+    }
+
+    pub fn record_load(&mut self, peer: PeerId, queue_depth: u32) {
+        self.stats.entry(peer).or_default().queue_depth = Some(queue_depth);
+    }
+
+    pub fn record_dispatch(&mut self, task_id: &str, peer: PeerId) {
+        self.in_flight.insert(task_id.to_string(), peer);
+    }
+
+    /// The provider a task in flight was dispatched to.
+    pub fn assignee(&self, task_id: &str) -> Option<PeerId> {
+        self.in_flight.get(task_id).copied()
+    }
+
+    /// Credits the provider a dispatched task was sent to; returns that provider.
+    pub fn record_outcome(&mut self, task_id: &str, success: bool) -> Option<PeerId> {
+        let peer = self.in_flight.remove(task_id)?;
+        let stats = self.stats.entry(peer).or_default();
+        if success {
+            stats.successes += 1;
+        } else {
+            stats.failures += 1;
+        }
+        Some(peer)
+    }
+
+    /// Seeds a peer's success history, e.g. from the contribution ledger.
+    pub fn record_history(&mut self, peer: PeerId, successes: u32, failures: u32) {
+        let stats = self.stats.entry(peer).or_default();
+        stats.successes += successes;
+        stats.failures += failures;
+    }
+
+    pub fn record_disagreement(&mut self, peer: PeerId) {
+        self.stats.entry(peer).or_default().disagreements += 1;
+    }
+
+    /// Picks up to `n` distinct providers, in selection order.
+    pub fn select_many(&mut self, candidates: &HashSet<PeerId>, prefs: &Preferences, n: usize) -> Vec<PeerId> {
+        let mut remaining = candidates.clone();
+        let mut chosen = Vec::new();
+        while chosen.len() < n {
+            match self.select(&remaining, prefs) {
+                Some(peer) => {
+                    remaining.remove(&peer);
+                    chosen.push(peer);
+                }
+                None => break,
+            }
+        }
+        chosen
+    }
+
+    pub fn select(&mut self, candidates: &HashSet<PeerId>, prefs: &Preferences) -> Option<PeerId> {
+        let mut eligible: Vec<PeerId> = candidates
+            .iter()
+            .filter(|p| !prefs.avoid.contains(&p.to_string()))
+            .filter(|p| match (prefs.max_latency_ms, self.stats.get(*p).and_then(|s| s.rtt)) {
+                (Some(max), Some(rtt)) => rtt.as_millis() <= max as u128,
+                _ => true,
+            })
+            .copied()
+            .collect();
+        let preferred: Vec<PeerId> = eligible
+            .iter()
+            .filter(|p| prefs.prefer.contains(&p.to_string()))
+            .copied()
+            .collect();
+        if !preferred.is_empty() {
+            eligible = preferred;
+        }
+        if eligible.is_empty() {
+            return None;
+        }
+        // Sort so every strategy is deterministic for a given candidate set.
+        eligible.sort_by_key(|p| p.to_string());
+
+        let empty = PeerStats::default();
+        let stats = |p: &PeerId| self.stats.get(p).unwrap_or(&empty);
+        let chosen = match prefs.strategy.unwrap_or(self.strategy) {
+            Strategy::RoundRobin => {
+                let peer = eligible[self.cursor % eligible.len()];
+                self.cursor = self.cursor.wrapping_add(1);
+                return Some(peer);
+            }
+            Strategy::LeastLoaded => eligible.iter().min_by_key(|p| {
+                let s = stats(*p);
+                (s.queue_depth.unwrap_or(u32::MAX), s.rtt.unwrap_or(Duration::MAX))
+            }),
+            Strategy::LowestLatency => eligible.iter().min_by_key(|p| stats(*p).rtt.unwrap_or(Duration::MAX)),
+            Strategy::Balanced => eligible.iter().max_by(|a, b| {
+                balanced_score(stats(*a)).total_cmp(&balanced_score(stats(*b)))
+            }),
+        };
+        chosen.copied()
+    }
+}
+
+fn balanced_score(stats: &PeerStats) -> f64 {
+    let queue = stats.queue_depth.unwrap_or(1) as f64;
+    let rtt_ms = stats.rtt.map(|d| d.as_secs_f64() * 1000.0).unwrap_or(100.0);
+    stats.success_rate() / ((1.0 + queue) * (1.0 + rtt_ms / 100.0))
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+/// Reassembles `task-chunk` messages for a delegated task. Gossip does not
+/// preserve ordering, so chunks are buffered until every earlier sequence
+/// number has arrived.
+#[derive(Debug, Default)]
+pub struct TaskStream {
+    next_seq: u64,
+    pending: BTreeMap<u64, String>,
+    text: String,
+    total: Option<u64>,
+    model: Option<String>,
+    output: Option<serde_json::Value>,
+}
+
+impl TaskStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts a chunk and returns the chunks that became deliverable, in order.
+    /// Duplicates of already delivered chunks are ignored.
+    pub fn push(&mut self, seq: u64, delta: String) -> Vec<(u64, String)> {
+        if seq < self.next_seq {
+            return Vec::new();
+        }
+        self.pending.insert(seq, delta);
+
+        let mut ready = Vec::new();
+        while let Some(delta) = self.pending.remove(&self.next_seq) {
+            self.text.push_str(&delta);
+            ready.push((self.next_seq, delta));
+            self.next_seq += 1;
+        }
+        ready
+    }
+
+    /// Records the completion marker: the task produced `total` chunks and,
+    /// for tool tasks, a structured `output`.
+    pub fn finish(&mut self, total: u64, model: Option<String>, output: Option<serde_json::Value>) {
+        self.total = Some(total);
+        self.model = model;
+        self.output = output;
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.total == Some(self.next_seq)
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    pub fn output(&self) -> Option<&serde_json::Value> {
+        self.output.as_ref()
+    }
+}
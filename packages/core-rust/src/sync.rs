@@ -1,8 +1,18 @@
-use yrs::{Doc, ReadTxn, WriteTxn, Transact, Update, Map, Text, TextRef};
+use crate::provenance::{self, DocRecord, KeyProvenance, UpdateEnvelope, Writer};
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use yrs::{Any, ArrayPrelim, DeepObservable, Doc, In, MapPrelim, MapRef, Options, Out, ReadTxn, Snapshot, StateVector, Subscription, TransactionMut, WriteTxn, Transact, Update, Map, Text, TextRef};
+use yrs::types::{EntryChange, Event, PathSegment};
 use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
 use serde::{Serialize, Deserialize};
-use sled::Db;
+use sled::{Db, Tree};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "kebab-case")]
@@ -12,6 +22,15 @@ pub enum SyncMessage {
         doc_id: String,
         delta: Vec<u8>,
         version: u64,
+        /// The sending peer's signature over `delta`, attributing it to them.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        envelope: Option<UpdateEnvelope>,
+    },
+    /// A signed creation or deletion of a document, applied by every peer
+    /// that finds it newer than what it knows and its signer authorized.
+    #[serde(rename = "doc-record")]
+    DocRecord {
+        record: DocRecord,
     },
     #[serde(rename = "discovery")]
     Discovery {
@@ -39,6 +58,56 @@ pub enum SyncMessage {
         assignee_id: String,
         payload: serde_json::Value,
         timestamp: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capability: Option<String>,
+    },
+    #[serde(rename = "task-graph")]
+    TaskGraph {
+        #[serde(rename = "graphId")]
+        graph_id: String,
+        #[serde(rename = "requesterId")]
+        requester_id: String,
+        steps: Vec<crate::graph::GraphStep>,
+        timestamp: u64,
+    },
+    #[serde(rename = "task-chunk")]
+    TaskChunk {
+        #[serde(rename = "taskId")]
+        task_id: String,
+        #[serde(rename = "requesterId")]
+        requester_id: String,
+        seq: u64,
+        delta: String,
+    },
+    #[serde(rename = "task-result")]
+    TaskResult {
+        #[serde(rename = "taskId")]
+        task_id: String,
+        #[serde(rename = "requesterId")]
+        requester_id: String,
+        status: String,
+        chunks: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        output: Option<serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        receipt: Option<crate::ledger::Receipt>,
+        /// Set when the answer was served from a result cache.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        provenance: Option<crate::cache::Provenance>,
+    },
+    /// Progress of a delegated task that has not produced output yet, e.g.
+    /// `awaiting-approval` while the provider waits for its operator.
+    #[serde(rename = "task-status")]
+    TaskStatus {
+        #[serde(rename = "taskId")]
+        task_id: String,
+        #[serde(rename = "requesterId")]
+        requester_id: String,
+        status: String,
     },
     #[serde(rename = "capability")]
     Capability {
@@ -46,6 +115,31 @@ pub enum SyncMessage {
         capability: String,
         metadata: serde_json::Value,
     },
+    #[serde(rename = "load")]
+    Load {
+        node_id: String,
+        queue_depth: u32,
+        timestamp: u64,
+    },
+    #[serde(rename = "call-for-proposals")]
+    CallForProposals {
+        #[serde(rename = "taskId")]
+        task_id: String,
+        #[serde(rename = "requesterId")]
+        requester_id: String,
+        capability: String,
+        #[serde(rename = "deadlineMs")]
+        deadline_ms: u64,
+        timestamp: u64,
+    },
+    #[serde(rename = "bid")]
+    Bid {
+        #[serde(rename = "taskId")]
+        task_id: String,
+        #[serde(rename = "requesterId")]
+        requester_id: String,
+        bid: crate::auction::Bid,
+    },
     #[serde(rename = "knowledge-update")]
     KnowledgeUpdate {
         key: String,
@@ -54,43 +148,691 @@ pub enum SyncMessage {
     Query(String),
 }
 
+/// ID the single shared document has always been broadcast under; it is
+/// stored in the default tree so existing state keeps loading.
+pub const DEFAULT_DOC: &str = "note1";
+
+/// Prefix of the sled tree holding every other document.
+const DOC_TREE_PREFIX: &str = "doc:";
+/// Sled tree holding the latest creation record and tombstone of each document.
+const RECORDS_TREE: &str = "doc-records";
+/// Documents a node holds before it stops creating more for peers, when not configured.
+pub const DEFAULT_MAX_DOCS: usize = 64;
+
+fn record_key(id: &str, deleted: bool) -> String {
+    format!("{}:{}", if deleted { "deleted" } else { "created" }, id)
+}
+
+#[derive(Debug, Clone)]
+pub enum DocError {
+    InvalidId(String),
+    AlreadyExists(String),
+    NotFound(String),
+    Deleted(String),
+    Limit(usize),
+    DefaultDoc,
+    Unauthorized(String),
+    Storage(String),
+}
+
+impl DocError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DocError::InvalidId(_) => "invalid-doc-id",
+            DocError::AlreadyExists(_) => "doc-exists",
+            DocError::NotFound(_) => "doc-not-found",
+            DocError::Deleted(_) => "doc-deleted",
+            DocError::Limit(_) => "doc-limit",
+            DocError::DefaultDoc => "default-doc",
+            DocError::Unauthorized(_) => "unauthorized",
+            DocError::Storage(_) => "storage",
+        }
+    }
+}
+
+impl fmt::Display for DocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocError::InvalidId(id) => write!(f, "invalid document ID '{}'", id),
+            DocError::AlreadyExists(id) => write!(f, "document '{}' already exists", id),
+            DocError::NotFound(id) => write!(f, "document '{}' does not exist", id),
+            DocError::Deleted(id) => write!(f, "document '{}' was deleted", id),
+            DocError::Limit(max) => write!(f, "this node already holds {} documents", max),
+            DocError::DefaultDoc => write!(f, "the default document cannot be deleted"),
+            DocError::Unauthorized(msg) => write!(f, "document record refused: {}", msg),
+            DocError::Storage(msg) => write!(f, "document storage error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DocError {}
+
+/// A top-level key of a document's shared map that changed in one transaction.
+/// `old_value` is only known when the key was replaced or removed; edits
+/// inside a value carry just the new one. `origin` is the peer the change came
+/// from, or `None` if it was made on this node.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyChange {
+    pub doc_id: String,
+    pub key: String,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+    pub origin: Option<String>,
+}
+
+/// Key changes buffered for slow subscribers before they start missing some.
+const KEY_CHANGE_CAPACITY: usize = 256;
+
+/// How often documents are snapshotted when not configured.
+pub const DEFAULT_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(3600);
+/// Periodic snapshots kept per document when not configured.
+pub const DEFAULT_SNAPSHOT_RETAIN: usize = 168;
+
+/// A stored point in a document's history. Snapshots taken on demand carry a
+/// label and are kept until the document is deleted; periodic ones are pruned.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotInfo {
+    pub id: u64,
+    pub created_at: u64,
+    pub label: Option<String>,
+}
+
+/// A key whose value differs between two points in a document's history.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyDiff {
+    pub key: String,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+}
+
+/// Opens, persists and routes updates to CRDT documents by ID. Each document
+/// lives in its own sled tree under `MESHCLAW_STATE_DIR`.
+pub struct DocManager {
+    db: Db,
+    docs: RwLock<HashMap<String, Arc<MemorySync>>>,
+    /// `created:<id>` and `deleted:<id>` -> the latest signed record of each kind.
+    records: Tree,
+    max_docs: usize,
+    /// Peers besides a document's creator whose deletions we accept.
+    admins: Vec<String>,
+    keypair: Keypair,
+    local_id: String,
+    key_changes: broadcast::Sender<KeyChange>,
+    snapshot_interval: Duration,
+    snapshot_retain: usize,
+}
+
+impl DocManager {
+    /// Opens the store and every document already in it. Changes made here
+    /// are attributed to the peer of `keypair`, which also signs creations
+    /// and deletions.
+    pub fn open(path: &Path, keypair: Keypair) -> anyhow::Result<Self> {
+        let local_id = &PeerId::from(keypair.public()).to_string();
+        std::fs::create_dir_all(path)?;
+        let db = sled::open(path)?;
+        let (key_changes, _) = broadcast::channel(KEY_CHANGE_CAPACITY);
+        let mut docs = HashMap::new();
+        let default_doc = MemorySync::open(DEFAULT_DOC, (*db).clone(), local_id, key_changes.clone())?;
+        docs.insert(DEFAULT_DOC.to_string(), Arc::new(default_doc));
+        for name in db.tree_names() {
+            if let Some(id) = name.strip_prefix(DOC_TREE_PREFIX.as_bytes()) {
+                let id = String::from_utf8_lossy(id).into_owned();
+                let doc = MemorySync::open(&id, db.open_tree(&name)?, local_id, key_changes.clone())?;
+                docs.insert(id, Arc::new(doc));
+            }
+        }
+        let records = db.open_tree(RECORDS_TREE)?;
+        Ok(DocManager {
+            db,
+            docs: RwLock::new(docs),
+            records,
+            max_docs: DEFAULT_MAX_DOCS,
+            admins: Vec::new(),
+            keypair,
+            local_id: local_id.to_string(),
+            key_changes,
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            snapshot_retain: DEFAULT_SNAPSHOT_RETAIN,
+        })
+    }
+
+    /// Stored under `MESHCLAW_STATE_DIR` (default `./.meshclaw/yrs-state`).
+    /// Documents are snapshotted every `MESHCLAW_SNAPSHOT_INTERVAL_SECS`
+    /// (default 3600), keeping the last `MESHCLAW_SNAPSHOT_RETAIN` (default 168).
+    /// Peers can make the node create documents until it holds
+    /// `MESHCLAW_MAX_DOCS` (default 64). Besides a document's creator, the
+    /// peers in `MESHCLAW_DOC_ADMINS` (comma-separated peer IDs) may delete it.
+    pub fn from_env(keypair: Keypair) -> anyhow::Result<Self> {
+        let path = std::env::var("MESHCLAW_STATE_DIR").unwrap_or_else(|_| "./.meshclaw/yrs-state".to_string());
+        let mut manager = DocManager::open(Path::new(&path), keypair)?;
+        if let Some(secs) = std::env::var("MESHCLAW_SNAPSHOT_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()) {
+            manager.snapshot_interval = Duration::from_secs(secs);
+        }
+        if let Some(retain) = std::env::var("MESHCLAW_SNAPSHOT_RETAIN").ok().and_then(|v| v.parse().ok()) {
+            manager.snapshot_retain = retain;
+        }
+        if let Some(max) = std::env::var("MESHCLAW_MAX_DOCS").ok().and_then(|v| v.parse().ok()) {
+            manager.max_docs = max;
+        }
+        if let Ok(admins) = std::env::var("MESHCLAW_DOC_ADMINS") {
+            manager.admins = admins.split(',').map(|a| a.trim().to_string()).filter(|a| !a.is_empty()).collect();
+        }
+        Ok(manager)
+    }
+
+    pub fn snapshot_interval(&self) -> Duration {
+        self.snapshot_interval
+    }
+
+    /// Changes to top-level keys of every document, as they are applied.
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<KeyChange> {
+        self.key_changes.subscribe()
+    }
+
+    pub fn default_doc(&self) -> Arc<MemorySync> {
+        self.docs.read().unwrap()[DEFAULT_DOC].clone()
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<MemorySync>> {
+        self.docs.read().unwrap().get(id).cloned()
+    }
+
+    /// Returns the document, creating it if needed. Used for updates from
+    /// peers, which may share documents we have not seen yet; deleted
+    /// documents stay deleted, and none are created past the document limit.
+    pub fn open_doc(&self, id: &str) -> Result<Arc<MemorySync>, DocError> {
+        if let Some(doc) = self.get(id) {
+            return Ok(doc);
+        }
+        if self.is_deleted(id) {
+            return Err(DocError::Deleted(id.to_string()));
+        }
+        self.insert(id, Some(self.max_docs))
+    }
+
+    /// Creates a document on request of our gateway, even one deleted before,
+    /// and returns the signed record that tells peers to bring it back too.
+    pub fn create(&self, id: &str) -> Result<DocRecord, DocError> {
+        if self.get(id).is_some() {
+            return Err(DocError::AlreadyExists(id.to_string()));
+        }
+        let record = self.sign(id, false)?;
+        self.insert(id, None)?;
+        self.store(&record)?;
+        Ok(record)
+    }
+
+    fn insert(&self, id: &str, max_docs: Option<usize>) -> Result<Arc<MemorySync>, DocError> {
+        if id.is_empty() || id.len() > 128 || id.chars().any(char::is_control) {
+            return Err(DocError::InvalidId(id.to_string()));
+        }
+        let mut docs = self.docs.write().unwrap();
+        if docs.contains_key(id) {
+            return Err(DocError::AlreadyExists(id.to_string()));
+        }
+        if let Some(max) = max_docs.filter(|max| docs.len() >= *max) {
+            return Err(DocError::Limit(max));
+        }
+        let tree = self
+            .db
+            .open_tree(format!("{}{}", DOC_TREE_PREFIX, id))
+            .map_err(|e| DocError::Storage(e.to_string()))?;
+        let doc = MemorySync::open(id, tree, &self.local_id, self.key_changes.clone()).map_err(|e| DocError::Storage(e.to_string()))?;
+        let doc = Arc::new(doc);
+        docs.insert(id.to_string(), doc.clone());
+        Ok(doc)
+    }
+
+    /// Removes a document on request of our gateway and returns the signed
+    /// tombstone that tells peers to remove it too. The tombstone keeps
+    /// updates from peers from bringing the document back.
+    pub fn delete(&self, id: &str) -> Result<DocRecord, DocError> {
+        if id == DEFAULT_DOC {
+            return Err(DocError::DefaultDoc);
+        }
+        if self.get(id).is_none() {
+            return Err(DocError::NotFound(id.to_string()));
+        }
+        let record = self.sign(id, true)?;
+        self.store(&record)?;
+        self.remove(id)?;
+        Ok(record)
+    }
+
+    /// Applies a creation or deletion from a peer. Returns whether it changed
+    /// anything; records older than what we know are ignored. Deleting, or
+    /// recreating a deleted document, takes this node, a `MESHCLAW_DOC_ADMINS`
+    /// peer or the peer that created or deleted the document last.
+    pub fn apply_record(&self, record: &DocRecord) -> Result<bool, DocError> {
+        if !record.verify() {
+            return Err(DocError::Unauthorized(format!("record for '{}' has an invalid signature", record.doc_id)));
+        }
+        if record.doc_id == DEFAULT_DOC {
+            return Err(DocError::DefaultDoc);
+        }
+        let id = &record.doc_id;
+        let created = self.record(id, false);
+        let tombstone = self.record(id, true);
+        let known = if record.deleted { &tombstone } else { &created };
+        if known.as_ref().is_some_and(|known| known.at >= record.at) {
+            return Ok(false);
+        }
+        let signed_last = |r: &Option<DocRecord>| r.as_ref().is_some_and(|r| r.peer_id == record.peer_id);
+        let authorized = record.peer_id == self.local_id
+            || self.admins.contains(&record.peer_id)
+            || signed_last(&created)
+            || signed_last(&tombstone);
+        let unclaimed = created.is_none() && tombstone.is_none();
+        if !authorized && (record.deleted || !unclaimed) {
+            return Err(DocError::Unauthorized(format!("{} may not change '{}'", record.peer_id, id)));
+        }
+
+        let created_at = created.map_or(0, |r| r.at);
+        let deleted_at = tombstone.map_or(0, |r| r.at);
+        if record.deleted {
+            self.store(record)?;
+            if record.at > created_at && self.get(id).is_some() {
+                self.remove(id)?;
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+        if record.at > deleted_at && self.get(id).is_none() {
+            self.insert(id, Some(self.max_docs))?;
+        }
+        self.store(record)?;
+        Ok(true)
+    }
+
+    /// Whether the document's latest tombstone is newer than its latest creation.
+    pub fn is_deleted(&self, id: &str) -> bool {
+        let created_at = self.record(id, false).map_or(0, |r| r.at);
+        self.record(id, true).is_some_and(|r| r.at > created_at)
+    }
+
+    /// Every creation record and tombstone, shared with peers on connect.
+    pub fn records(&self) -> Vec<DocRecord> {
+        self.records
+            .iter()
+            .values()
+            .filter_map(|v| serde_json::from_slice(&v.ok()?).ok())
+            .collect()
+    }
+
+    fn record(&self, id: &str, deleted: bool) -> Option<DocRecord> {
+        let bytes = self.records.get(record_key(id, deleted)).ok()??;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    fn store(&self, record: &DocRecord) -> Result<(), DocError> {
+        let bytes = serde_json::to_vec(record).map_err(|e| DocError::Storage(e.to_string()))?;
+        self.records
+            .insert(record_key(&record.doc_id, record.deleted), bytes)
+            .map_err(|e| DocError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Signs a record dated now, or just after the document's last record if
+    /// our clock is behind it, so the new record supersedes it.
+    fn sign(&self, id: &str, deleted: bool) -> Result<DocRecord, DocError> {
+        let after = [self.record(id, false), self.record(id, true)].into_iter().flatten().map(|r| r.at).max().unwrap_or(0);
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+        DocRecord::sign(&self.keypair, id, deleted, now.max(after + 1))
+            .ok_or_else(|| DocError::Storage("failed to sign document record".to_string()))
+    }
+
+    /// Drops a document and its stored state.
+    fn remove(&self, id: &str) -> Result<(), DocError> {
+        self.docs.write().unwrap().remove(id);
+        self.db
+            .drop_tree(format!("{}{}", DOC_TREE_PREFIX, id))
+            .map_err(|e| DocError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.docs.read().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Compacts every document, reporting the last failure.
+    pub fn compact(&self) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for (id, doc) in self.docs.read().unwrap().iter() {
+            if let Err(e) = doc.compact() {
+                result = Err(e.context(format!("failed to compact document '{}'", id)));
+            }
+        }
+        result
+    }
+
+    /// Snapshots every document that changed since its last snapshot and
+    /// prunes old periodic ones, reporting the last failure.
+    pub fn snapshot(&self) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for (id, doc) in self.docs.read().unwrap().iter() {
+            if let Err(e) = doc.snapshot_if_changed(self.snapshot_retain) {
+                result = Err(e.context(format!("failed to snapshot document '{}'", id)));
+            }
+        }
+        result
+    }
+
+    /// Removes expired keys from every document, reporting the last failure.
+    pub fn sweep_expired(&self) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for (id, doc) in self.docs.read().unwrap().iter() {
+            match doc.sweep_expired() {
+                Ok(expired) if !expired.is_empty() => println!("Expired {} from '{}'", expired.join(", "), id),
+                Ok(_) => {},
+                Err(e) => result = Err(e.context(format!("failed to expire keys of document '{}'", id))),
+            }
+        }
+        result
+    }
+}
+
+/// Transaction origin of updates received from peers, followed by the peer ID.
+/// These updates are not rebroadcast.
+const REMOTE_ORIGIN: &str = "remote:";
+/// Like `REMOTE_ORIGIN`, for unsigned updates whose writers are unverified.
+const UNVERIFIED_ORIGIN: &str = "unverified:";
+
+/// Name of the root map every document keeps its keys in.
+const SHARED_MAP: &str = "shared";
+/// Root map of key expiry times in Unix milliseconds. Expiry is part of the
+/// document, so every peer removes a key at the same moment.
+const EXPIRY_MAP: &str = "expires";
+
+/// Key of a document's compacted state.
+const SNAPSHOT_KEY: &str = "state";
+/// Update log entries are keyed by this prefix and a big-endian sequence number.
+const LOG_PREFIX: &[u8] = b"log:";
+
+fn log_key(seq: u64) -> Vec<u8> {
+    [LOG_PREFIX, &seq.to_be_bytes()].concat()
+}
+
+fn log_seq(key: &[u8]) -> u64 {
+    key.get(LOG_PREFIX.len()..)
+        .and_then(|b| b.try_into().ok())
+        .map(u64::from_be_bytes)
+        .unwrap_or(0)
+}
+
+/// Encoded yrs snapshots are keyed by this prefix and their big-endian ID,
+/// and their `SnapshotInfo` by the second one.
+const HISTORY_PREFIX: &[u8] = b"history:";
+const HISTORY_INFO_PREFIX: &[u8] = b"history-info:";
+
+fn history_key(prefix: &[u8], id: u64) -> Vec<u8> {
+    [prefix, &id.to_be_bytes()].concat()
+}
+
+/// What a key held at a snapshot, to be written back by a restore.
+enum PastValue {
+    Text(String),
+    Json(serde_json::Value),
+    Removed,
+}
+
+/// One CRDT document with its persisted state. Every update is appended to
+/// an update log as soon as it is applied, and `compact` folds the log into
+/// the snapshot. Local changes are also collected so only they need to be
+/// broadcast. Deleted content is kept while a retained snapshot can still
+/// read it, and collected once the snapshots older than its deletion are
+/// pruned. Each change to a key is recorded with the yrs clients that made
+/// it, which signed envelopes map to peers.
 pub struct MemorySync {
     doc: Doc,
-    db: Db,
+    tree: Tree,
+    local_id: String,
+    changes: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// Updates applied but not yet appended to the log.
+    unsaved: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// Sequence number of the next log entry; held while writing the log.
+    next_seq: Mutex<u64>,
+    _subscription: Subscription,
+    _key_subscription: Subscription,
 }
 
 impl MemorySync {
-    pub fn new() -> Self {
-        let default_path = "./.meshclaw/yrs-state".to_string();
-        let path_str = std::env::var("MESHCLAW_STATE_DIR").unwrap_or(default_path);
-        let path = Path::new(&path_str);
-        std::fs::create_dir_all(path).expect("Failed to create state directory");
-        let db = sled::open(path).expect("Failed to open sled database");
-        
-        let doc = Doc::new();
-        if let Ok(Some(bytes)) = db.get("state") {
+    /// Loads the snapshot and replays the update log written since.
+    fn open(doc_id: &str, tree: Tree, local_id: &str, key_changes: broadcast::Sender<KeyChange>) -> anyhow::Result<Self> {
+        // Garbage is collected explicitly when snapshots are pruned.
+        let doc = Doc::with_options(Options { skip_gc: true, ..Options::default() });
+        provenance::map_client(&tree, doc.client_id(), local_id)?;
+        let shared = doc.get_or_insert_map(SHARED_MAP);
+        let mut next_seq = 0;
+        {
             let mut txn = doc.transact_mut();
-            if let Ok(update) = Update::decode_v1(&bytes) {
-                let _ = txn.apply_update(update);
+            if let Some(bytes) = tree.get(SNAPSHOT_KEY)? {
+                let _ = txn.apply_update(Update::decode_v1(&bytes)?);
+            }
+            for entry in tree.scan_prefix(LOG_PREFIX) {
+                let (key, bytes) = entry?;
+                next_seq = log_seq(&key) + 1;
+                match Update::decode_v1(&bytes) {
+                    Ok(update) => {
+                        let _ = txn.apply_update(update);
+                    },
+                    Err(e) => eprintln!("Skipping unreadable update log entry {}: {}", log_seq(&key), e),
+                }
             }
         }
-        
-        MemorySync { doc, db }
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let unsaved = Arc::new(Mutex::new(Vec::new()));
+        let (local_sink, log_sink) = (changes.clone(), unsaved.clone());
+        let subscription = doc
+            .observe_update_v1(move |txn, event| {
+                if remote_peer(txn).is_none() {
+                    local_sink.lock().unwrap().push(event.update.clone());
+                }
+                log_sink.lock().unwrap().push(event.update.clone());
+            })
+            .map_err(|e| anyhow::anyhow!("cannot observe document updates: {:?}", e))?;
+        let doc_id = doc_id.to_string();
+        let root = shared.clone();
+        let (writer_tree, writer_id) = (tree.clone(), local_id.to_string());
+        let key_subscription = shared.observe_deep(move |txn, events| {
+            let origin = remote_peer(txn);
+            let mut changed = BTreeMap::new();
+            for event in events.iter() {
+                match (event.path().front(), event) {
+                    (None, Event::Map(event)) => {
+                        for (key, change) in event.keys(txn) {
+                            let (old_value, new_value) = match change {
+                                EntryChange::Inserted(new) => (None, Some(new)),
+                                EntryChange::Updated(old, new) => (Some(old), Some(new)),
+                                EntryChange::Removed(old) => (Some(old), None),
+                            };
+                            let to_json = |value: &Out| any_to_json(&value.to_json(txn));
+                            changed.insert(key.to_string(), (old_value.map(to_json), new_value.map(to_json)));
+                        }
+                    },
+                    (Some(PathSegment::Key(key)), _) => {
+                        let new_value = root.get(txn, key).map(|value| any_to_json(&value.to_json(txn)));
+                        changed.entry(key.to_string()).or_insert((None, new_value));
+                    },
+                    _ => {},
+                }
+            }
+
+            let writers = txn_writers(txn, &writer_tree, origin.as_deref().unwrap_or(&writer_id), !is_unverified(txn));
+            for key in changed.keys() {
+                if let Err(e) = provenance::record(&writer_tree, key, &writers) {
+                    eprintln!("Failed to record writers of '{}' in '{}': {}", key, doc_id, e);
+                }
+            }
+            if key_changes.receiver_count() == 0 {
+                return;
+            }
+            for (key, (old_value, new_value)) in changed {
+                let _ = key_changes.send(KeyChange {
+                    doc_id: doc_id.clone(),
+                    key,
+                    old_value,
+                    new_value,
+                    origin: origin.clone(),
+                });
+            }
+        });
+        Ok(MemorySync {
+            doc,
+            tree,
+            local_id: local_id.to_string(),
+            changes,
+            unsaved,
+            next_seq: Mutex::new(next_seq),
+            _subscription: subscription,
+            _key_subscription: key_subscription,
+        })
     }
 
-    pub fn save(&self) {
-        let txn = self.doc.transact();
-        let update = txn.encode_state_as_update_v1(&yrs::StateVector::default());
-        let _ = self.db.insert("state", update).expect("Failed to save state");
-        let _ = self.db.flush().expect("Failed to flush db");
+    /// Appends updates applied since the last call to the log and flushes it.
+    fn persist(&self) -> anyhow::Result<()> {
+        let mut next_seq = self.next_seq.lock().unwrap();
+        let updates = std::mem::take(&mut *self.unsaved.lock().unwrap());
+        if updates.is_empty() {
+            return Ok(());
+        }
+        for update in updates {
+            self.tree.insert(log_key(*next_seq), update)?;
+            *next_seq += 1;
+        }
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    /// Replaces the snapshot with the current state and clears the update log.
+    pub fn compact(&self) -> anyhow::Result<()> {
+        self.write_state(false)
     }
 
+    /// Like `compact`, but also rewrites the snapshot when nothing was logged
+    /// since the last one, e.g. after garbage was collected.
+    fn write_state(&self, always: bool) -> anyhow::Result<()> {
+        let _log = self.next_seq.lock().unwrap();
+        let mut batch = sled::Batch::default();
+        let mut logged = 0;
+        for key in self.tree.scan_prefix(LOG_PREFIX).keys() {
+            batch.remove(key?);
+            logged += 1;
+        }
+        if logged == 0 && !always && self.tree.contains_key(SNAPSHOT_KEY)? {
+            return Ok(());
+        }
+        batch.insert(SNAPSHOT_KEY, self.get_update());
+        self.tree.apply_batch(batch)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    /// Applies an update made on this node, e.g. by the gateway; it is
+    /// broadcast with the other local changes. The gateway is trusted, so
+    /// the clients that made it are mapped to this node.
     pub fn apply_update(&self, update_data: Vec<u8>) -> anyhow::Result<()> {
-        let mut txn = self.doc.transact_mut();
-        let _ = txn.apply_update(Update::decode_v1(&update_data)?);
+        for (client, _) in Update::decode_v1(&update_data)?.state_vector().iter() {
+            provenance::map_client(&self.tree, *client, &self.local_id)?;
+        }
+        {
+            let mut txn = self.doc.transact_mut();
+            let _ = txn.apply_update(Update::decode_v1(&update_data)?);
+        }
+        self.persist()
+    }
+
+    /// Applies an update received from `peer`. Returns true if it depends on
+    /// changes we have not seen, in which case the missing ones should be
+    /// requested with our state vector. Writers of updates that were not
+    /// `signed` are recorded as unverified.
+    pub fn apply_remote(&self, update_data: &[u8], peer: &str, signed: bool) -> anyhow::Result<bool> {
+        let missing = {
+            let origin = format!("{}{}", if signed { REMOTE_ORIGIN } else { UNVERIFIED_ORIGIN }, peer);
+            let mut txn = self.doc.transact_mut_with(origin.as_str());
+            let _ = txn.apply_update(Update::decode_v1(update_data)?);
+            txn.has_missing_updates()
+        };
+        self.persist()?;
+        Ok(missing)
+    }
+
+    /// Fails unless `client_id` and every client `update` carries blocks for
+    /// is unclaimed or already mapped to `peer_id`, so a signer cannot pass
+    /// off blocks under another peer's client as that peer's writes.
+    pub fn check_clients(&self, peer_id: &str, client_id: u64, update: &[u8]) -> anyhow::Result<()> {
+        let clients = Update::decode_v1(update)?.state_vector();
+        for client in clients.iter().map(|(client, _)| *client).chain(std::iter::once(client_id)) {
+            if let Some(owner) = provenance::peer_of(&self.tree, client).filter(|owner| owner != peer_id) {
+                anyhow::bail!("it carries changes under client {} of {}", client, owner);
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps the yrs client `peer_id` signed for to that peer. Only the signed
+    /// client is mapped: an update can carry changes relayed from other
+    /// clients, which their own envelopes have to claim.
+    pub fn attribute(&self, peer_id: &str, client_id: u64) -> anyhow::Result<()> {
+        provenance::map_client(&self.tree, client_id, peer_id)?;
         Ok(())
     }
 
+    /// The yrs client ID changes made on this node carry.
+    pub fn client_id(&self) -> u64 {
+        self.doc.client_id()
+    }
+
+    /// Who changed `key` and when.
+    pub fn provenance(&self, key: &str) -> Option<KeyProvenance> {
+        provenance::read(&self.tree, key)
+    }
+
+    /// Local changes since the last call, merged into one update.
+    pub fn take_changes(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        let changes = std::mem::take(&mut *self.changes.lock().unwrap());
+        if changes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(yrs::merge_updates_v1(&changes)?))
+    }
+
+    /// Encoded state vector, sent to peers to ask for what we are missing.
+    pub fn state_vector(&self) -> Vec<u8> {
+        self.doc.transact().state_vector().encode_v1()
+    }
+
+    /// Changes a peer with the given state vector is missing. Deletions do
+    /// not advance clocks, so the update always carries our whole delete set,
+    /// even when the peer has every insertion we have.
+    pub fn diff(&self, state_vector: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let theirs = StateVector::decode_v1(state_vector)?;
+        Ok(self.doc.transact().encode_state_as_update_v1(&theirs))
+    }
+
+    /// Encoded yrs snapshot (state vector and delete set), sent to peers so
+    /// they can tell whether we hold changes they lack, deletions included.
+    pub fn snapshot_state(&self) -> Vec<u8> {
+        self.doc.transact().snapshot().encode_v1()
+    }
+
+    /// Whether a peer with the given encoded snapshot has changes we lack:
+    /// insertions past our clocks, or a delete set that differs from ours.
+    pub fn is_behind(&self, snapshot: &[u8]) -> bool {
+        let theirs = match Snapshot::decode_v1(snapshot) {
+            Ok(snapshot) => snapshot,
+            Err(_) => return false,
+        };
+        let ours = self.doc.transact().snapshot();
+        theirs.state_map.iter().any(|(client, clock)| ours.state_map.get(client) < *clock)
+            || theirs.delete_set != ours.delete_set
+    }
+
     pub fn get_update(&self) -> Vec<u8> {
         let txn = self.doc.transact();
         txn.encode_state_as_update_v1(&yrs::StateVector::default())
@@ -98,28 +840,526 @@ impl MemorySync {
 
     pub fn get_text(&self, key: &str) -> Option<String> {
         let txn = self.doc.transact();
-        let map = txn.get_map("shared")?;
+        let map = txn.get_map(SHARED_MAP)?;
         let val = map.get(&txn, key)?;
         Some(val.to_string(&txn))
     }
 
     pub fn get_keys(&self) -> Vec<String> {
         let txn = self.doc.transact();
-        if let Some(map) = txn.get_map("shared") {
+        if let Some(map) = txn.get_map(SHARED_MAP) {
             map.keys(&txn).collect()
         } else {
             Vec::new()
         }
     }
 
-    pub fn insert_text(&self, key: &str, value: &str) {
-        let mut txn = self.doc.transact_mut();
-        let map = txn.get_or_insert_map("shared");
-        let text: TextRef = map.get_or_init(&mut txn, key);
-        let len = text.len(&txn);
-        if len > 0 {
-            text.remove_range(&mut txn, 0, len);
+    /// Reads any value under `key` as JSON; text reads back as a string.
+    pub fn get_json(&self, key: &str) -> Option<serde_json::Value> {
+        let txn = self.doc.transact();
+        let map = txn.get_map(SHARED_MAP)?;
+        Some(any_to_json(&map.get(&txn, key)?.to_json(&txn)))
+    }
+
+    /// Stores `value` under `key` as nested yrs maps and arrays, replacing
+    /// whatever was there.
+    pub fn set_json(&self, key: &str, value: &serde_json::Value) -> anyhow::Result<()> {
+        {
+            let mut txn = self.doc.transact_mut();
+            let map = txn.get_or_insert_map(SHARED_MAP);
+            map.insert(&mut txn, key, json_to_prelim(value));
         }
-        text.push(&mut txn, value);
+        self.persist()
+    }
+
+    /// Merges an object into the map under `key` field by field, recursing
+    /// into nested objects, so concurrent writes to different fields all
+    /// survive. Any other value replaces what was there.
+    pub fn merge_json(&self, key: &str, value: &serde_json::Value) -> anyhow::Result<()> {
+        {
+            let mut txn = self.doc.transact_mut();
+            let map = txn.get_or_insert_map(SHARED_MAP);
+            merge_field(&mut txn, &map, key, value);
+        }
+        self.persist()
+    }
+
+    /// Sets the text under `key` to `value` by applying only the edits that
+    /// differ from the current text, so concurrent edits elsewhere survive.
+    pub fn update_text(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        {
+            let mut txn = self.doc.transact_mut();
+            let map = txn.get_or_insert_map(SHARED_MAP);
+            write_text(&mut txn, &map, key, value);
+        }
+        self.persist()
+    }
+
+    /// Replaces `delete` characters at character position `index` of the
+    /// text under `key` with `insert`.
+    pub fn splice_text(&self, key: &str, index: usize, delete: usize, insert: &str) -> anyhow::Result<()> {
+        // Checked before anything is written, so a rejected edit leaves the
+        // key as it was.
+        let len = {
+            let txn = self.doc.transact();
+            match txn.get_map(SHARED_MAP).and_then(|map| map.get(&txn, key)) {
+                Some(Out::YText(text)) => text.get_string(&txn).chars().count(),
+                Some(_) => anyhow::bail!("'{}' does not hold text", key),
+                None => 0,
+            }
+        };
+        let end = match index.checked_add(delete) {
+            Some(end) if end <= len => end,
+            _ => anyhow::bail!("range {}+{} is beyond the end of '{}' ({} characters)", index, delete, key, len),
+        };
+        {
+            let mut txn = self.doc.transact_mut();
+            let map = txn.get_or_insert_map(SHARED_MAP);
+            let text: TextRef = match map.get(&txn, key) {
+                Some(Out::YText(text)) => text,
+                _ => map.insert(&mut txn, key, yrs::TextPrelim::new("")),
+            };
+            let current = text.get_string(&txn);
+            let start = byte_offset(&current, index);
+            let end = byte_offset(&current, end);
+            if end > start {
+                text.remove_range(&mut txn, start as u32, (end - start) as u32);
+            }
+            if !insert.is_empty() {
+                text.insert(&mut txn, start as u32, insert);
+            }
+        }
+        self.persist()
+    }
+
+    pub fn insert_text_at(&self, key: &str, index: usize, insert: &str) -> anyhow::Result<()> {
+        self.splice_text(key, index, 0, insert)
+    }
+
+    pub fn delete_text(&self, key: &str, index: usize, len: usize) -> anyhow::Result<()> {
+        self.splice_text(key, index, len, "")
+    }
+
+    /// Removes `key` on every peer. Returns false if it did not exist.
+    pub fn delete_key(&self, key: &str) -> anyhow::Result<bool> {
+        let removed = {
+            let mut txn = self.doc.transact_mut();
+            let map = txn.get_or_insert_map(SHARED_MAP);
+            let expiry = txn.get_or_insert_map(EXPIRY_MAP);
+            expiry.remove(&mut txn, key);
+            map.remove(&mut txn, key).is_some()
+        };
+        self.persist()?;
+        Ok(removed)
+    }
+
+    /// Makes `key` expire `ttl` from now, or never when `None`.
+    pub fn set_ttl(&self, key: &str, ttl: Option<Duration>) -> anyhow::Result<()> {
+        {
+            let mut txn = self.doc.transact_mut();
+            let expiry = txn.get_or_insert_map(EXPIRY_MAP);
+            match ttl {
+                Some(ttl) => {
+                    let expires_at = now_millis() + ttl.as_millis() as u64;
+                    expiry.insert(&mut txn, key, Any::Number(expires_at as f64));
+                },
+                None => {
+                    expiry.remove(&mut txn, key);
+                },
+            }
+        }
+        self.persist()
+    }
+
+    /// When `key` expires, in Unix milliseconds.
+    pub fn expires_at(&self, key: &str) -> Option<u64> {
+        let txn = self.doc.transact();
+        match txn.get_map(EXPIRY_MAP)?.get(&txn, key)? {
+            Out::Any(Any::Number(ms)) => Some(ms as u64),
+            _ => None,
+        }
+    }
+
+    /// Removes keys whose expiry has passed, along with their expiry, and
+    /// returns them. Peers sweeping the same key concurrently converge since
+    /// they all delete the same entry.
+    pub fn sweep_expired(&self) -> anyhow::Result<Vec<String>> {
+        let now = now_millis();
+        let expired: Vec<String> = {
+            let txn = self.doc.transact();
+            let Some(expiry) = txn.get_map(EXPIRY_MAP) else {
+                return Ok(Vec::new());
+            };
+            expiry
+                .iter(&txn)
+                .filter(|(_, at)| matches!(at, Out::Any(Any::Number(ms)) if (*ms as u64) <= now))
+                .map(|(key, _)| key.to_string())
+                .collect()
+        };
+        if expired.is_empty() {
+            return Ok(expired);
+        }
+        {
+            let mut txn = self.doc.transact_mut();
+            let map = txn.get_or_insert_map(SHARED_MAP);
+            let expiry = txn.get_or_insert_map(EXPIRY_MAP);
+            for key in &expired {
+                map.remove(&mut txn, key);
+                expiry.remove(&mut txn, key);
+            }
+        }
+        self.persist()?;
+        Ok(expired)
+    }
+
+    /// Stores a snapshot of the current state, even if nothing changed.
+    pub fn snapshot(&self, label: Option<&str>) -> anyhow::Result<SnapshotInfo> {
+        let snapshot = self.doc.transact().snapshot().encode_v1();
+        self.store_snapshot(snapshot, label.map(|l| l.to_string()))
+    }
+
+    /// Stores an unlabelled snapshot if the document changed since the last
+    /// one, then drops the oldest unlabelled snapshots beyond `retain` and
+    /// collects the content only they could read.
+    pub fn snapshot_if_changed(&self, retain: usize) -> anyhow::Result<Option<SnapshotInfo>> {
+        let snapshot = self.doc.transact().snapshot().encode_v1();
+        let latest = self.tree.scan_prefix(HISTORY_PREFIX).values().next_back().transpose()?;
+        if latest.as_deref() == Some(&snapshot[..]) {
+            return Ok(None);
+        }
+        let info = self.store_snapshot(snapshot, None)?;
+
+        let periodic: Vec<u64> = self.snapshots().into_iter().filter(|s| s.label.is_none()).map(|s| s.id).collect();
+        let pruned = periodic.len().saturating_sub(retain);
+        let mut batch = sled::Batch::default();
+        for id in periodic.iter().take(pruned) {
+            batch.remove(history_key(HISTORY_PREFIX, *id));
+            batch.remove(history_key(HISTORY_INFO_PREFIX, *id));
+        }
+        self.tree.apply_batch(batch)?;
+        if pruned > 0 {
+            self.collect_garbage()?;
+        }
+        Ok(Some(info))
+    }
+
+    /// Discards the content of everything deleted before the oldest retained
+    /// snapshot, which no snapshot can read any more, and rewrites the stored
+    /// state without it. Peers that catch up from us receive the smaller state.
+    fn collect_garbage(&self) -> anyhow::Result<()> {
+        let oldest = self.tree.scan_prefix(HISTORY_PREFIX).values().next().transpose()?;
+        let deleted = match oldest {
+            Some(bytes) => Snapshot::decode_v1(&bytes)?.delete_set,
+            None => self.doc.transact().snapshot().delete_set,
+        };
+        self.doc.transact_mut().gc(Some(&deleted));
+        self.write_state(true)
+    }
+
+    fn store_snapshot(&self, snapshot: Vec<u8>, label: Option<String>) -> anyhow::Result<SnapshotInfo> {
+        let now = now_millis();
+        // IDs are creation times in milliseconds, bumped past the last one if needed.
+        let last = self.snapshots().last().map(|s| s.id).unwrap_or(0);
+        let id = now.max(last + 1);
+        let info = SnapshotInfo { id, created_at: now / 1000, label };
+        let mut batch = sled::Batch::default();
+        batch.insert(history_key(HISTORY_PREFIX, id), snapshot);
+        batch.insert(history_key(HISTORY_INFO_PREFIX, id), serde_json::to_vec(&info)?);
+        self.tree.apply_batch(batch)?;
+        self.tree.flush()?;
+        Ok(info)
+    }
+
+    /// Stored snapshots, oldest first.
+    pub fn snapshots(&self) -> Vec<SnapshotInfo> {
+        self.tree
+            .scan_prefix(HISTORY_INFO_PREFIX)
+            .values()
+            .filter_map(|v| serde_json::from_slice(&v.ok()?).ok())
+            .collect()
+    }
+
+    /// The document as it was at snapshot `id`, in a separate doc.
+    fn doc_at(&self, id: u64) -> anyhow::Result<Doc> {
+        let bytes = self
+            .tree
+            .get(history_key(HISTORY_PREFIX, id))?
+            .ok_or_else(|| anyhow::anyhow!("no snapshot {}", id))?;
+        let snapshot = Snapshot::decode_v1(&bytes)?;
+        let update = self
+            .doc
+            .transact()
+            .encode_state_from_snapshot_v1(&snapshot)
+            .map_err(|e| anyhow::anyhow!("cannot read snapshot {}: {:?}", id, e))?;
+        let past = Doc::new();
+        let _ = past.transact_mut().apply_update(Update::decode_v1(&update)?);
+        Ok(past)
+    }
+
+    /// Reads `key` as JSON at snapshot `id`.
+    pub fn get_json_at(&self, id: u64, key: &str) -> anyhow::Result<Option<serde_json::Value>> {
+        let past = self.doc_at(id)?;
+        Ok(read_values(&past, Some(key)).remove(key))
+    }
+
+    /// Keys that differ between snapshot `from` and snapshot `to`, or the
+    /// current state when `to` is `None`.
+    pub fn diff_snapshots(&self, from: u64, to: Option<u64>) -> anyhow::Result<Vec<KeyDiff>> {
+        let mut old = read_values(&self.doc_at(from)?, None);
+        let mut new = match to {
+            Some(to) => read_values(&self.doc_at(to)?, None),
+            None => read_values(&self.doc, None),
+        };
+        let keys: std::collections::BTreeSet<String> = old.keys().chain(new.keys()).cloned().collect();
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| {
+                let (old_value, new_value) = (old.remove(&key), new.remove(&key));
+                (old_value != new_value).then_some(KeyDiff { key, old_value, new_value })
+            })
+            .collect())
+    }
+
+    /// Writes `key`, or every key when `None`, back to its value at snapshot
+    /// `id` as a new change, so peers receive the restore like any other
+    /// edit. Text is restored with minimal edits; keys that did not exist
+    /// yet are removed.
+    pub fn restore(&self, id: u64, key: Option<&str>) -> anyhow::Result<()> {
+        let past = self.doc_at(id)?;
+        let restored: Vec<(String, PastValue)> = {
+            let past_txn = past.transact();
+            let past_map = past_txn.get_map(SHARED_MAP);
+            let keys: Vec<String> = match key {
+                Some(key) => vec![key.to_string()],
+                None => {
+                    let mut keys = self.get_keys();
+                    keys.extend(past_map.iter().flat_map(|map| map.keys(&past_txn).map(|k| k.to_string()).collect::<Vec<_>>()));
+                    keys.sort();
+                    keys.dedup();
+                    keys
+                },
+            };
+            keys.into_iter()
+                .map(|key| {
+                    let value = match past_map.as_ref().and_then(|map| map.get(&past_txn, &key)) {
+                        Some(Out::YText(text)) => PastValue::Text(text.get_string(&past_txn)),
+                        Some(value) => PastValue::Json(any_to_json(&value.to_json(&past_txn))),
+                        None => PastValue::Removed,
+                    };
+                    (key, value)
+                })
+                .collect()
+        };
+        {
+            let mut txn = self.doc.transact_mut();
+            let map = txn.get_or_insert_map(SHARED_MAP);
+            for (key, value) in restored {
+                match value {
+                    PastValue::Text(text) => write_text(&mut txn, &map, &key, &text),
+                    PastValue::Json(value) => {
+                        map.insert(&mut txn, key.as_str(), json_to_prelim(&value));
+                    },
+                    PastValue::Removed => {
+                        map.remove(&mut txn, &key);
+                    },
+                }
+            }
+        }
+        self.persist()
+    }
+}
+
+/// Sets the text under `key` to `value`, applying only the edits that differ
+/// from the current text. A value of another type is replaced by text.
+fn write_text(txn: &mut TransactionMut, map: &MapRef, key: &str, value: &str) {
+    let text: TextRef = match map.get(&*txn, key) {
+        Some(Out::YText(text)) => text,
+        _ => map.insert(txn, key, yrs::TextPrelim::new("")),
+    };
+    let current = text.get_string(&*txn);
+    // Applied back to front so earlier positions stay valid.
+    for edit in diff_text(&current, value).into_iter().rev() {
+        let start = byte_offset(&current, edit.index);
+        let end = byte_offset(&current, edit.index + edit.delete);
+        if end > start {
+            text.remove_range(txn, start as u32, (end - start) as u32);
+        }
+        if !edit.insert.is_empty() {
+            text.insert(txn, start as u32, &edit.insert);
+        }
+    }
+}
+
+/// Values of `key`, or of every key, in a document's shared map as JSON.
+fn read_values(doc: &Doc, key: Option<&str>) -> HashMap<String, serde_json::Value> {
+    let txn = doc.transact();
+    let Some(map) = txn.get_map(SHARED_MAP) else {
+        return HashMap::new();
+    };
+    match key {
+        Some(key) => map.get(&txn, key).map(|v| (key.to_string(), any_to_json(&v.to_json(&txn)))).into_iter().collect(),
+        None => map.iter(&txn).map(|(k, v)| (k.to_string(), any_to_json(&v.to_json(&txn)))).collect(),
+    }
+}
+
+/// Largest old × new character product diffed exactly; bigger changes
+/// become a single splice between the common prefix and suffix.
+const MAX_DIFF_CELLS: usize = 1 << 20;
+
+/// Replaces `delete` characters at character `index` of the old text with `insert`.
+#[derive(Debug)]
+struct TextEdit {
+    index: usize,
+    delete: usize,
+    insert: String,
+}
+
+/// Edits turning `old` into `new`, in ascending order of position. Positions
+/// refer to the old text.
+fn diff_text(old: &str, new: &str) -> Vec<TextEdit> {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    if a.is_empty() && b.is_empty() {
+        return Vec::new();
+    }
+    if a.len().saturating_mul(b.len()) > MAX_DIFF_CELLS {
+        return vec![TextEdit { index: prefix, delete: a.len(), insert: b.iter().collect() }];
+    }
+
+    // lcs[i * width + j] is the length of the longest common subsequence of a[i..] and b[j..].
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut edits: Vec<TextEdit> = Vec::new();
+    let mut pending: Option<TextEdit> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            edits.extend(pending.take());
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let edit = pending.get_or_insert_with(|| TextEdit { index: prefix + i, delete: 0, insert: String::new() });
+        if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            edit.delete += 1;
+            i += 1;
+        } else {
+            edit.insert.push(b[j]);
+            j += 1;
+        }
+    }
+    edits.extend(pending);
+    edits
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+/// Writers of a transaction: the clients whose clocks it advanced, resolved
+/// to peers where known. A transaction that only deleted content advances no
+/// clock and is attributed to `peer_id`, the peer it came from. Writers of
+/// an unverified transaction are left unresolved.
+fn txn_writers(txn: &TransactionMut, tree: &Tree, peer_id: &str, verified: bool) -> Vec<Writer> {
+    let timestamp = now_millis() / 1000;
+    let before = txn.before_state();
+    let writers: Vec<Writer> = txn
+        .after_state()
+        .iter()
+        .filter(|(client, clock)| before.get(client) < **clock)
+        .map(|(client, _)| Writer {
+            peer_id: provenance::peer_of(tree, *client).filter(|_| verified),
+            client_id: Some(*client),
+            timestamp,
+            unverified: !verified,
+        })
+        .collect();
+    if writers.is_empty() {
+        let peer_id = Some(peer_id.to_string()).filter(|_| verified);
+        return vec![Writer { peer_id, client_id: None, timestamp, unverified: !verified }];
+    }
+    writers
+}
+
+/// The peer a transaction's changes were received from, if any.
+fn remote_peer(txn: &TransactionMut) -> Option<String> {
+    let origin = txn.origin()?;
+    let origin = origin.as_ref();
+    let peer = origin
+        .strip_prefix(REMOTE_ORIGIN.as_bytes())
+        .or_else(|| origin.strip_prefix(UNVERIFIED_ORIGIN.as_bytes()))?;
+    Some(String::from_utf8_lossy(peer).into_owned())
+}
+
+fn is_unverified(txn: &TransactionMut) -> bool {
+    txn.origin().is_some_and(|origin| origin.as_ref().starts_with(UNVERIFIED_ORIGIN.as_bytes()))
+}
+
+fn byte_offset(text: &str, char_index: usize) -> usize {
+    text.char_indices().nth(char_index).map(|(i, _)| i).unwrap_or(text.len())
+}
+
+fn merge_field(txn: &mut TransactionMut, map: &MapRef, key: &str, value: &serde_json::Value) {
+    match (map.get(&*txn, key), value) {
+        (Some(Out::YMap(child)), serde_json::Value::Object(fields)) => {
+            for (field, v) in fields {
+                merge_field(txn, &child, field, v);
+            }
+        },
+        _ => {
+            map.insert(txn, key, json_to_prelim(value));
+        },
+    }
+}
+
+fn json_to_prelim(value: &serde_json::Value) -> In {
+    match value {
+        serde_json::Value::Object(fields) => {
+            In::Map(fields.iter().map(|(k, v)| (k.as_str(), json_to_prelim(v))).collect::<MapPrelim>())
+        },
+        serde_json::Value::Array(items) => In::Array(items.iter().map(json_to_prelim).collect::<ArrayPrelim>()),
+        serde_json::Value::Null => In::Any(Any::Null),
+        serde_json::Value::Bool(b) => In::Any(Any::Bool(*b)),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            // Yjs numbers are doubles; only integers beyond 2^53 need a BigInt.
+            Some(i) if i.unsigned_abs() > MAX_SAFE_INTEGER => In::Any(Any::BigInt(i)),
+            _ => In::Any(Any::Number(n.as_f64().unwrap_or_default())),
+        },
+        serde_json::Value::String(s) => In::Any(Any::String(s.as_str().into())),
+    }
+}
+
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+fn any_to_json(any: &Any) -> serde_json::Value {
+    match any {
+        Any::Null | Any::Undefined => serde_json::Value::Null,
+        Any::Bool(b) => serde_json::Value::Bool(*b),
+        Any::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER as f64 => serde_json::json!(*n as i64),
+        Any::Number(n) => serde_json::json!(n),
+        Any::BigInt(i) => serde_json::json!(i),
+        Any::String(s) => serde_json::Value::String(s.to_string()),
+        Any::Buffer(bytes) => serde_json::json!(bytes.as_ref()),
+        Any::Array(items) => serde_json::Value::Array(items.iter().map(any_to_json).collect()),
+        Any::Map(fields) => serde_json::Value::Object(fields.iter().map(|(k, v)| (k.clone(), any_to_json(v))).collect()),
     }
 }
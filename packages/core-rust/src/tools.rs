@@ -0,0 +1,375 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub enum ToolError {
+    UnknownTool(String),
+    InvalidInput(String),
+    Denied(String),
+    Failed(String),
+}
+
+impl ToolError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ToolError::UnknownTool(_) => "unknown-tool",
+            ToolError::InvalidInput(_) => "invalid-input",
+            ToolError::Denied(_) => "denied",
+            ToolError::Failed(_) => "failed",
+        }
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolError::UnknownTool(name) => write!(f, "no tool named '{}' is registered", name),
+            ToolError::InvalidInput(e) => write!(f, "invalid tool input: {}", e),
+            ToolError::Denied(e) => write!(f, "tool call denied: {}", e),
+            ToolError::Failed(e) => write!(f, "tool failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+/// A capability served as `tool:<name>`. Input is validated against
+/// `input_schema` before `call` runs; output is structured JSON.
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    fn input_schema(&self) -> serde_json::Value;
+    fn call<'a>(&'a self, input: serde_json::Value) -> BoxFuture<'a, Result<serde_json::Value, ToolError>>;
+}
+
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: HashMap<String, Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the built-in tools enabled through the environment:
+    /// `MESHCLAW_FILE_SEARCH_ROOT` enables `file-search` under that directory and
+    /// `MESHCLAW_SHELL_ALLOWLIST` (comma-separated programs) enables `shell`.
+    pub fn from_env() -> Self {
+        let mut registry = ToolRegistry::new();
+        if let Ok(root) = std::env::var("MESHCLAW_FILE_SEARCH_ROOT") {
+            registry.register(Arc::new(FileSearchTool::new(root)));
+        }
+        if let Ok(list) = std::env::var("MESHCLAW_SHELL_ALLOWLIST") {
+            let allowlist: Vec<String> = list
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if !allowlist.is_empty() {
+                let workdir = std::env::var("MESHCLAW_SHELL_WORKDIR").unwrap_or_else(|_| ".".to_string());
+                registry.register(Arc::new(ShellTool::new(allowlist, workdir)));
+            }
+        }
+        registry
+    }
+
+    pub fn register(&mut self, tool: Arc<dyn Tool>) {
+        self.tools.insert(tool.name().to_string(), tool);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.tools.get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tools.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub async fn call(&self, name: &str, input: serde_json::Value) -> Result<serde_json::Value, ToolError> {
+        let tool = self.get(name).ok_or_else(|| ToolError::UnknownTool(name.to_string()))?;
+        validate(&tool.input_schema(), &input, "input").map_err(ToolError::InvalidInput)?;
+        tool.call(input).await
+    }
+}
+
+/// Checks `value` against the subset of JSON Schema tools declare: `type`,
+/// `properties`, `required`, `additionalProperties: false`, `items` and `enum`.
+pub fn validate(schema: &serde_json::Value, value: &serde_json::Value, path: &str) -> Result<(), String> {
+    if let Some(allowed) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !allowed.contains(value) {
+            return Err(format!("{} must be one of {}", path, serde_json::Value::Array(allowed.clone())));
+        }
+    }
+
+    if let Some(ty) = schema.get("type").and_then(|t| t.as_str()) {
+        let ok = match ty {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        if !ok {
+            return Err(format!("{} must be of type {}", path, ty));
+        }
+    }
+
+    if let Some(obj) = value.as_object() {
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        for key in schema.get("required").and_then(|r| r.as_array()).into_iter().flatten() {
+            if let Some(key) = key.as_str() {
+                if !obj.contains_key(key) {
+                    return Err(format!("{}.{} is required", path, key));
+                }
+            }
+        }
+        for (key, field) in obj {
+            match properties.and_then(|p| p.get(key)) {
+                Some(field_schema) => validate(field_schema, field, &format!("{}.{}", path, key))?,
+                None if schema.get("additionalProperties") == Some(&serde_json::Value::Bool(false)) => {
+                    return Err(format!("{}.{} is not allowed", path, key));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Some(items), Some(arr)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in arr.iter().enumerate() {
+            validate(items, item, &format!("{}[{}]", path, i))?;
+        }
+    }
+
+    Ok(())
+}
+
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+fn truncate(bytes: &[u8]) -> String {
+    let end = bytes.len().min(MAX_OUTPUT_BYTES);
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Searches file names and contents below a fixed root directory.
+pub struct FileSearchTool {
+    root: PathBuf,
+}
+
+impl FileSearchTool {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileSearchTool { root: root.into() }
+    }
+}
+
+impl Tool for FileSearchTool {
+    fn name(&self) -> &str {
+        "file-search"
+    }
+
+    fn description(&self) -> &str {
+        "Find files below the shared directory whose name or contents contain a query string"
+    }
+
+    fn input_schema(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string" },
+                "path": { "type": "string" },
+                "maxResults": { "type": "integer" }
+            },
+            "required": ["query"],
+            "additionalProperties": false
+        })
+    }
+
+    fn call<'a>(&'a self, input: serde_json::Value) -> BoxFuture<'a, Result<serde_json::Value, ToolError>> {
+        Box::pin(async move {
+            let query = input["query"].as_str().unwrap_or_default().to_lowercase();
+            if query.is_empty() {
+                return Err(ToolError::InvalidInput("input.query must not be empty".to_string()));
+            }
+            let max_results = input["maxResults"].as_u64().unwrap_or(50).min(500) as usize;
+
+            let root = self.root.canonicalize().map_err(|e| ToolError::Failed(e.to_string()))?;
+            let start = root
+                .join(input["path"].as_str().unwrap_or(""))
+                .canonicalize()
+                .map_err(|e| ToolError::InvalidInput(e.to_string()))?;
+            if !start.starts_with(&root) {
+                return Err(ToolError::Denied("path escapes the search root".to_string()));
+            }
+
+            let matches = tokio::task::spawn_blocking(move || search_files(&root, &start, &query, max_results))
+                .await
+                .map_err(|e| ToolError::Failed(e.to_string()))?;
+            Ok(serde_json::json!({ "matches": matches }))
+        })
+    }
+}
+
+fn search_files(root: &Path, start: &Path, query: &str, max_results: usize) -> Vec<serde_json::Value> {
+    let mut matches = Vec::new();
+    let mut stack = vec![start.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            if matches.len() >= max_results {
+                return matches;
+            }
+            let path = entry.path();
+            // Symlinks are skipped so the search cannot leave the root.
+            let file_type = match entry.file_type() {
+                Ok(t) if !t.is_symlink() => t,
+                _ => continue,
+            };
+            if file_type.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let rel = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().into_owned();
+            if rel.to_lowercase().contains(query) {
+                matches.push(serde_json::json!({ "path": rel }));
+                continue;
+            }
+            if let Ok(content) = std::fs::read_to_string(&path) {
+                if let Some((n, line)) = content.lines().enumerate().find(|(_, l)| l.to_lowercase().contains(query)) {
+                    matches.push(serde_json::json!({ "path": rel, "line": n + 1, "text": line.trim() }));
+                }
+            }
+        }
+    }
+    matches
+}
+
+/// Runs allowlisted programs directly (no shell) in the working directory,
+/// with a cleared environment and a timeout. Arguments that are absolute
+/// paths or contain `..` are refused, which keeps path arguments inside the
+/// working directory; it does not isolate the programs themselves, so only
+/// allowlist programs that are safe to run with arbitrary relative arguments.
+pub struct ShellTool {
+    allowlist: Vec<String>,
+    workdir: PathBuf,
+    timeout: Duration,
+}
+
+impl ShellTool {
+    pub fn new(allowlist: Vec<String>, workdir: impl Into<PathBuf>) -> Self {
+        ShellTool { allowlist, workdir: workdir.into(), timeout: Duration::from_secs(30) }
+    }
+}
+
+impl Tool for ShellTool {
+    fn name(&self) -> &str {
+        "shell"
+    }
+
+    fn description(&self) -> &str {
+        "Run an allowlisted command with relative path arguments in the shared working directory"
+    }
+
+    fn input_schema(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string", "enum": self.allowlist },
+                "args": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["command"],
+            "additionalProperties": false
+        })
+    }
+
+    fn call<'a>(&'a self, input: serde_json::Value) -> BoxFuture<'a, Result<serde_json::Value, ToolError>> {
+        Box::pin(async move {
+            let command = input["command"].as_str().unwrap_or_default();
+            if !self.allowlist.iter().any(|c| c == command) {
+                return Err(ToolError::Denied(format!("'{}' is not allowlisted", command)));
+            }
+            let args: Vec<&str> = input["args"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|a| a.as_str())
+                .collect();
+            if let Some(arg) = args.iter().find(|a| escapes_workdir(a)) {
+                return Err(ToolError::Denied(format!("argument '{}' leaves the working directory", arg)));
+            }
+
+            let mut cmd = tokio::process::Command::new(command);
+            cmd.args(&args)
+                .current_dir(&self.workdir)
+                .env_clear()
+                .env("PATH", std::env::var("PATH").unwrap_or_default())
+                .stdin(std::process::Stdio::null())
+                .kill_on_drop(true);
+
+            let output = tokio::time::timeout(self.timeout, cmd.output())
+                .await
+                .map_err(|_| ToolError::Failed(format!("timed out after {}s", self.timeout.as_secs())))?
+                .map_err(|e| ToolError::Failed(e.to_string()))?;
+
+            Ok(serde_json::json!({
+                "exitCode": output.status.code(),
+                "stdout": truncate(&output.stdout),
+                "stderr": truncate(&output.stderr)
+            }))
+        })
+    }
+}
+
+/// Whether an argument names an absolute path or steps up with `..`. Past the
+/// leading dashes, a path may start anywhere after a run of short flags
+/// (`-rf/abs`, `-C..`) or after a `=` or `,` separator, so every such
+/// position is checked.
+fn escapes_workdir(arg: &str) -> bool {
+    let rest = arg.trim_start_matches('-');
+    let after_flags = rest.char_indices().take_while(|(_, c)| c.is_ascii_alphanumeric()).map(|(i, _)| i + 1);
+    let after_separators = rest.match_indices(['=', ',']).map(|(i, _)| i + 1);
+    std::iter::once(0).chain(after_flags).chain(after_separators).any(|start| {
+        let path = Path::new(&rest[start..]);
+        path.has_root() || path.components().any(|c| c == std::path::Component::ParentDir)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_attached_to_short_flags_are_denied() {
+        assert!(escapes_workdir("-rf/abs"));
+        assert!(escapes_workdir("-C.."));
+        assert!(escapes_workdir("-xvf../archive.tar"));
+    }
+
+    #[test]
+    fn paths_after_separators_are_denied() {
+        assert!(escapes_workdir("--x=../y"));
+        assert!(escapes_workdir("--include=src,/etc"));
+        assert!(escapes_workdir("--/etc"));
+    }
+
+    #[test]
+    fn paths_inside_the_workdir_are_allowed() {
+        assert!(!escapes_workdir("src/main.rs"));
+        assert!(!escapes_workdir("-la"));
+        assert!(!escapes_workdir("-o./out/file"));
+        assert!(!escapes_workdir("--output=build/out"));
+        assert!(!escapes_workdir("a..b"));
+        assert!(!escapes_workdir("https://example.com/x"));
+    }
+}
@@ -57,6 +57,10 @@ impl VectorDb {
         self.table.add(batches).execute().await.unwrap();
     }
 
+    pub async fn row_count(&self) -> anyhow::Result<usize> {
+        Ok(self.table.count_rows(None).await?)
+    }
+
     #[allow(dead_code)]
     pub async fn search(&self, query_vec: Vec<f32>, limit: usize) -> Vec<(String, f32)> {
         let mut stream = self.table
@@ -0,0 +1,184 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Decides whether two replica results count as the same answer.
+pub trait Comparator: Send + Sync {
+    fn equivalent(&self, a: &serde_json::Value, b: &serde_json::Value) -> bool;
+}
+
+pub struct ExactComparator;
+
+impl Comparator for ExactComparator {
+    fn equivalent(&self, a: &serde_json::Value, b: &serde_json::Value) -> bool {
+        a == b
+    }
+}
+
+/// Compares strings ignoring case and whitespace differences; other values exactly.
+pub struct NormalizedTextComparator;
+
+impl Comparator for NormalizedTextComparator {
+    fn equivalent(&self, a: &serde_json::Value, b: &serde_json::Value) -> bool {
+        match (a.as_str(), b.as_str()) {
+            (Some(a), Some(b)) => normalize(a) == normalize(b),
+            _ => a == b,
+        }
+    }
+}
+
+fn normalize(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+pub struct ComparatorRegistry {
+    comparators: HashMap<String, Arc<dyn Comparator>>,
+}
+
+impl ComparatorRegistry {
+    /// Registry with the built-in `exact` and `normalized` comparators.
+    pub fn new() -> Self {
+        let mut registry = ComparatorRegistry { comparators: HashMap::new() };
+        registry.register("exact", Arc::new(ExactComparator));
+        registry.register("normalized", Arc::new(NormalizedTextComparator));
+        registry
+    }
+
+    pub fn register(&mut self, name: &str, comparator: Arc<dyn Comparator>) {
+        self.comparators.insert(name.to_string(), comparator);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Comparator>> {
+        self.comparators.get(name).cloned()
+    }
+}
+
+impl Default for ComparatorRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum VoteRule {
+    /// Every replica must return an equivalent answer.
+    Unanimous,
+    /// More than half of the replicas must return an equivalent answer.
+    #[default]
+    Majority,
+}
+
+fn default_comparator() -> String {
+    "exact".to_string()
+}
+
+fn default_timeout_secs() -> u64 {
+    120
+}
+
+/// Requester options read from `payload.redundancy` of a `Delegate`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Redundancy {
+    pub replicas: usize,
+    #[serde(default)]
+    pub vote: VoteRule,
+    #[serde(default = "default_comparator")]
+    pub comparator: String,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Redundancy {
+    /// Returns the options only when more than one replica is requested.
+    pub fn from_payload(payload: &serde_json::Value) -> Option<Self> {
+        payload
+            .get("redundancy")
+            .and_then(|v| serde_json::from_value::<Redundancy>(v.clone()).ok())
+            .filter(|r| r.replicas > 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VoteOutcome {
+    pub accepted: Option<serde_json::Value>,
+    pub agreeing: Vec<PeerId>,
+    pub dissenting: Vec<PeerId>,
+    /// Replicas that failed or never answered.
+    pub failed: Vec<PeerId>,
+}
+
+/// Collects replica results for one task and decides the accepted answer.
+pub struct VoteRound {
+    rule: VoteRule,
+    comparator: Arc<dyn Comparator>,
+    pending: Vec<PeerId>,
+    results: Vec<(PeerId, Option<serde_json::Value>)>,
+    deadline: Instant,
+}
+
+impl VoteRound {
+    pub fn new(rule: VoteRule, comparator: Arc<dyn Comparator>, replicas: Vec<PeerId>, timeout: Duration) -> Self {
+        VoteRound { rule, comparator, pending: replicas, results: Vec::new(), deadline: Instant::now() + timeout }
+    }
+
+    /// Records a replica's answer, or `None` if it failed.
+    pub fn record(&mut self, peer: PeerId, result: Option<serde_json::Value>) {
+        if let Some(pos) = self.pending.iter().position(|p| *p == peer) {
+            self.pending.remove(pos);
+            self.results.push((peer, result));
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+
+    /// Groups answers into equivalence classes and accepts the largest one if
+    /// it satisfies the rule. Replicas still pending count as failed.
+    pub fn decide(&self) -> VoteOutcome {
+        let expected = self.results.len() + self.pending.len();
+        let mut failed: Vec<PeerId> = self.pending.clone();
+        let mut classes: Vec<(serde_json::Value, Vec<PeerId>)> = Vec::new();
+        for (peer, result) in &self.results {
+            let value = match result {
+                Some(value) => value,
+                None => {
+                    failed.push(*peer);
+                    continue;
+                }
+            };
+            match classes.iter_mut().find(|(rep, _)| self.comparator.equivalent(rep, value)) {
+                Some((_, peers)) => peers.push(*peer),
+                None => classes.push((value.clone(), vec![*peer])),
+            }
+        }
+
+        let best = classes.iter().enumerate().max_by_key(|(_, (_, peers))| peers.len()).map(|(i, _)| i);
+        let accepted_class = best.filter(|&i| {
+            let votes = classes[i].1.len();
+            match self.rule {
+                VoteRule::Unanimous => votes == expected,
+                VoteRule::Majority => votes * 2 > expected,
+            }
+        });
+
+        let mut outcome = VoteOutcome { accepted: None, agreeing: Vec::new(), dissenting: Vec::new(), failed };
+        for (i, (value, peers)) in classes.into_iter().enumerate() {
+            if Some(i) == accepted_class {
+                outcome.accepted = Some(value);
+                outcome.agreeing = peers;
+            } else {
+                outcome.dissenting.extend(peers);
+            }
+        }
+        outcome
+    }
+}
@@ -2,14 +2,20 @@ use tokio::net::TcpListener;
 use tokio_tungstenite::accept_async;
 use futures_util::{StreamExt, SinkExt};
 use std::sync::Arc;
-use meshclaw_core::sync::MemorySync;
+use meshclaw_core::approval::{ApprovalGate, Decision};
+use meshclaw_core::ledger::Ledger;
+use meshclaw_core::quota::QuotaTracker;
+use meshclaw_core::sync::{DocManager, MemorySync, SyncMessage, DEFAULT_DOC};
 use tokio_tungstenite::tungstenite::protocol::Message;
 
 pub async fn run_ws_server(
-    memory: Arc<MemorySync>, 
+    docs: Arc<DocManager>,
     gateway_to_rust_tx: tokio::sync::mpsc::Sender<meshclaw_core::sync::SyncMessage>,
     rust_to_gateway_tx: tokio::sync::broadcast::Sender<serde_json::Value>,
     peers: Arc<tokio::sync::RwLock<std::collections::HashSet<String>>>,
+    ledger: Arc<Ledger>,
+    quotas: Arc<QuotaTracker>,
+    approvals: Arc<ApprovalGate>,
 ) {
     let port = std::env::var("MESHCLAW_BRIDGE_PORT").unwrap_or_else(|_| "3001".to_string());
     let addr = format!("127.0.0.1:{}", port);
@@ -17,14 +23,19 @@ pub async fn run_ws_server(
     println!("WebSocket bridge listening on ws://{}", addr);
 
     while let Ok((stream, _)) = listener.accept().await {
-        let memory = memory.clone();
+        let docs = docs.clone();
         let gateway_to_rust_tx = gateway_to_rust_tx.clone();
         let mut rust_to_gateway_rx = rust_to_gateway_tx.subscribe();
         let peers = peers.clone();
+        let ledger = ledger.clone();
+        let quotas = quotas.clone();
+        let approvals = approvals.clone();
         
         tokio::spawn(async move {
             let mut ws_stream = accept_async(stream).await.expect("Error during ws handshake");
             println!("New WebSocket connection");
+            // Memory change events are only sent for these (document, key prefix) pairs.
+            let mut subscriptions: Vec<(String, String)> = Vec::new();
 
             loop {
                 tokio::select! {
@@ -39,11 +50,16 @@ pub async fn run_ws_server(
                             if let Ok(json) = serde_json::from_str::<serde_json::Value>(text) {
                                 let id = json.get("id").and_then(|v| v.as_str()).map(|s| s.to_string());
                                 let method = json.get("method").and_then(|v| v.as_str());
+                                let doc_id = json.get("params").and_then(|p| p.get("docId")).and_then(|d| d.as_str());
+                                let memory = match doc_id {
+                                    Some(doc_id) => docs.get(doc_id),
+                                    None => Some(docs.default_doc()),
+                                };
                                 
                                 match method {
                                     Some("query") => {
                                         let key = json.get("params").and_then(|p| p.get("key")).and_then(|k| k.as_str()).unwrap_or("");
-                                        let result = memory.get_text(key).unwrap_or_else(|| "null".to_string());
+                                        let result = memory.and_then(|m| m.get_text(key)).unwrap_or_else(|| "null".to_string());
                                         let response = serde_json::json!({ "id": id, "result": result });
                                         let _ = ws_stream.send(Message::Text(response.to_string())).await;
                                     },
@@ -52,11 +68,198 @@ pub async fn run_ws_server(
                                         let response = serde_json::json!({ "id": id, "result": list });
                                         let _ = ws_stream.send(Message::Text(response.to_string())).await;
                                     },
+                                    Some("ledger") | Some("mesh:ledger") => {
+                                        let peer = json.get("params").and_then(|p| p.get("peerId")).and_then(|k| k.as_str());
+                                        let result = match peer {
+                                            Some(peer) => serde_json::json!(ledger.account(peer)),
+                                            None => serde_json::json!(ledger.accounts()),
+                                        };
+                                        let response = serde_json::json!({ "id": id, "result": result });
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some("usage") | Some("mesh:usage") => {
+                                        let peer = json.get("params").and_then(|p| p.get("peerId")).and_then(|k| k.as_str());
+                                        let result = match peer {
+                                            Some(peer) => {
+                                                let (hour, day) = quotas.usage(peer);
+                                                serde_json::json!({ "hour": hour, "day": day })
+                                            },
+                                            None => quotas.report(),
+                                        };
+                                        let response = serde_json::json!({ "id": id, "result": result });
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some("approvals") | Some("mesh:approvals") => {
+                                        let response = serde_json::json!({ "id": id, "result": approvals.pending() });
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some(m @ ("approve" | "mesh:approve" | "reject" | "mesh:reject")) => {
+                                        let params = json.get("params");
+                                        let approval_id = params.and_then(|p| p.get("approvalId")).and_then(|t| t.as_str()).unwrap_or("");
+                                        let decision = if m.ends_with("approve") {
+                                            Decision::Approved
+                                        } else {
+                                            Decision::Rejected(params.and_then(|p| p.get("reason")).and_then(|r| r.as_str()).map(|r| r.to_string()))
+                                        };
+                                        let response = if approvals.decide(approval_id, decision) {
+                                            serde_json::json!({ "id": id, "result": "ok" })
+                                        } else {
+                                            serde_json::json!({ "id": id, "error": format!("no approval '{}' is pending", approval_id) })
+                                        };
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some("mesh:json:get") => {
+                                        let key = json.get("params").and_then(|p| p.get("key")).and_then(|k| k.as_str()).unwrap_or("");
+                                        let result = memory.and_then(|m| m.get_json(key)).unwrap_or(serde_json::Value::Null);
+                                        let response = serde_json::json!({ "id": id, "result": result });
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some(m @ ("mesh:json:set" | "mesh:json:merge")) => {
+                                        let params = json.get("params");
+                                        let key = params.and_then(|p| p.get("key")).and_then(|k| k.as_str()).unwrap_or("");
+                                        let value = params.and_then(|p| p.get("value")).cloned().unwrap_or(serde_json::Value::Null);
+                                        let outcome = match memory {
+                                            Some(_) if key.is_empty() => Err("missing key".to_string()),
+                                            Some(memory) if m.ends_with("merge") => memory.merge_json(key, &value).map_err(|e| e.to_string()),
+                                            Some(memory) => memory.set_json(key, &value).map_err(|e| e.to_string()),
+                                            None => Err(format!("document '{}' does not exist", doc_id.unwrap_or(""))),
+                                        };
+                                        let response = match outcome {
+                                            Ok(()) => serde_json::json!({ "id": id, "result": "ok" }),
+                                            Err(e) => serde_json::json!({ "id": id, "error": e }),
+                                        };
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some(m @ ("mesh:text:update" | "mesh:text:splice" | "mesh:text:insert" | "mesh:text:delete")) => {
+                                        let params = json.get("params");
+                                        let key = params.and_then(|p| p.get("key")).and_then(|k| k.as_str()).unwrap_or("");
+                                        let outcome = match memory {
+                                            Some(_) if key.is_empty() => Err("missing key".to_string()),
+                                            Some(memory) => edit_text(&memory, m, key, params),
+                                            None => Err(format!("document '{}' does not exist", doc_id.unwrap_or(""))),
+                                        };
+                                        let response = match outcome {
+                                            Ok(()) => serde_json::json!({ "id": id, "result": "ok" }),
+                                            Err(e) => serde_json::json!({ "id": id, "error": e }),
+                                        };
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some(m @ ("delete" | "mesh:delete" | "expire" | "mesh:expire")) => {
+                                        let params = json.get("params");
+                                        let key = params.and_then(|p| p.get("key")).and_then(|k| k.as_str()).unwrap_or("");
+                                        let outcome = match memory {
+                                            Some(_) if key.is_empty() => Err("missing key".to_string()),
+                                            Some(memory) if m.ends_with("delete") => match memory.delete_key(key) {
+                                                Ok(true) => Ok(serde_json::json!("ok")),
+                                                Ok(false) => Err(format!("key '{}' does not exist", key)),
+                                                Err(e) => Err(e.to_string()),
+                                            },
+                                            Some(memory) => {
+                                                // A missing or zero `ttlSecs` clears the expiry.
+                                                let ttl = params
+                                                    .and_then(|p| p.get("ttlSecs"))
+                                                    .and_then(|t| t.as_u64())
+                                                    .filter(|secs| *secs > 0)
+                                                    .map(std::time::Duration::from_secs);
+                                                memory
+                                                    .set_ttl(key, ttl)
+                                                    .map(|()| serde_json::json!({ "expiresAt": memory.expires_at(key) }))
+                                                    .map_err(|e| e.to_string())
+                                            },
+                                            None => Err(format!("document '{}' does not exist", doc_id.unwrap_or(""))),
+                                        };
+                                        let response = match outcome {
+                                            Ok(result) => serde_json::json!({ "id": id, "result": result }),
+                                            Err(e) => serde_json::json!({ "id": id, "error": e }),
+                                        };
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some("provenance") | Some("mesh:provenance") => {
+                                        let key = json.get("params").and_then(|p| p.get("key")).and_then(|k| k.as_str()).unwrap_or("");
+                                        let result = memory.and_then(|m| m.provenance(key));
+                                        let response = serde_json::json!({ "id": id, "result": result });
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
                                     Some("keys") | Some("mesh:keys") => {
-                                        let list = memory.get_keys();
+                                        let list = memory.map(|m| m.get_keys()).unwrap_or_default();
                                         let response = serde_json::json!({ "id": id, "result": list });
                                         let _ = ws_stream.send(Message::Text(response.to_string())).await;
                                     },
+                                    Some("mesh:snapshots") => {
+                                        let response = match memory {
+                                            Some(memory) => serde_json::json!({ "id": id, "result": memory.snapshots() }),
+                                            None => serde_json::json!({ "id": id, "error": format!("document '{}' does not exist", doc_id.unwrap_or("")) }),
+                                        };
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some(m @ ("mesh:snapshot" | "mesh:snapshot:get" | "mesh:snapshot:diff" | "mesh:snapshot:restore")) => {
+                                        let params = json.get("params");
+                                        let key = params.and_then(|p| p.get("key")).and_then(|k| k.as_str());
+                                        let snapshot_param = |name: &str| params.and_then(|p| p.get(name)).and_then(|v| v.as_u64());
+                                        let outcome = match (memory, snapshot_param("snapshotId")) {
+                                            (None, _) => Err(format!("document '{}' does not exist", doc_id.unwrap_or(""))),
+                                            (Some(memory), _) if m == "mesh:snapshot" => {
+                                                let label = params.and_then(|p| p.get("label")).and_then(|l| l.as_str());
+                                                memory.snapshot(label).map(|info| serde_json::json!(info)).map_err(|e| e.to_string())
+                                            },
+                                            (Some(memory), _) if m == "mesh:snapshot:diff" => match snapshot_param("from") {
+                                                Some(from) => memory
+                                                    .diff_snapshots(from, snapshot_param("to"))
+                                                    .map(|diff| serde_json::json!(diff))
+                                                    .map_err(|e| e.to_string()),
+                                                None => Err("missing from".to_string()),
+                                            },
+                                            (Some(_), None) => Err("missing snapshotId".to_string()),
+                                            (Some(memory), Some(snapshot_id)) if m == "mesh:snapshot:get" => match key {
+                                                Some(key) => memory
+                                                    .get_json_at(snapshot_id, key)
+                                                    .map(|value| value.unwrap_or(serde_json::Value::Null))
+                                                    .map_err(|e| e.to_string()),
+                                                None => Err("missing key".to_string()),
+                                            },
+                                            (Some(memory), Some(snapshot_id)) => memory
+                                                .restore(snapshot_id, key)
+                                                .map(|()| serde_json::json!("ok"))
+                                                .map_err(|e| e.to_string()),
+                                        };
+                                        let response = match outcome {
+                                            Ok(result) => serde_json::json!({ "id": id, "result": result }),
+                                            Err(e) => serde_json::json!({ "id": id, "error": e }),
+                                        };
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some(m @ ("subscribe" | "mesh:subscribe" | "unsubscribe" | "mesh:unsubscribe")) => {
+                                        let prefix = json.get("params").and_then(|p| p.get("prefix")).and_then(|k| k.as_str()).unwrap_or("");
+                                        let subscription = (doc_id.unwrap_or(DEFAULT_DOC).to_string(), prefix.to_string());
+                                        if m.ends_with("unsubscribe") {
+                                            subscriptions.retain(|s| *s != subscription);
+                                        } else if !subscriptions.contains(&subscription) {
+                                            subscriptions.push(subscription);
+                                        }
+                                        let response = serde_json::json!({ "id": id, "result": "ok" });
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some("docs") | Some("mesh:docs") => {
+                                        let response = serde_json::json!({ "id": id, "result": docs.list() });
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
+                                    Some(m @ ("docs:create" | "mesh:docs:create" | "docs:delete" | "mesh:docs:delete")) => {
+                                        let doc_id = doc_id.unwrap_or("");
+                                        let outcome = if m.ends_with("create") {
+                                            docs.create(doc_id)
+                                        } else {
+                                            docs.delete(doc_id)
+                                        };
+                                        // Peers follow the signed record, so the change holds mesh-wide.
+                                        if let Ok(record) = &outcome {
+                                            let _ = gateway_to_rust_tx.send(SyncMessage::DocRecord { record: record.clone() }).await;
+                                        }
+                                        let response = match outcome {
+                                            Ok(_) => serde_json::json!({ "id": id, "result": "ok" }),
+                                            Err(e) => serde_json::json!({ "id": id, "error": e.to_string() }),
+                                        };
+                                        let _ = ws_stream.send(Message::Text(response.to_string())).await;
+                                    },
                                     Some("broadcast") => {
                                         if let Some(params) = json.get("params") {
                                             if let Ok(sync_msg) = serde_json::from_value::<meshclaw_core::sync::SyncMessage>(params.clone()) {
@@ -72,10 +275,14 @@ pub async fn run_ws_server(
                                         if let Ok(sync_msg) = serde_json::from_value::<meshclaw_core::sync::SyncMessage>(json.clone()) {
                                             match &sync_msg {
                                                 meshclaw_core::sync::SyncMessage::KnowledgeUpdate { key, value } => {
-                                                    memory.insert_text(key, value);
+                                                    if let Err(e) = docs.default_doc().update_text(key, value) {
+                                                        eprintln!("Failed to store knowledge update: {e}");
+                                                    }
                                                 },
-                                                meshclaw_core::sync::SyncMessage::MemorySync { delta, .. } => {
-                                                    let _ = memory.apply_update(delta.clone());
+                                                meshclaw_core::sync::SyncMessage::MemorySync { doc_id, delta, .. } => {
+                                                    if let Ok(doc) = docs.open_doc(doc_id) {
+                                                        let _ = doc.apply_update(delta.clone());
+                                                    }
                                                 },
                                                 _ => {}
                                             }
@@ -88,7 +295,17 @@ pub async fn run_ws_server(
                     }
                     mesh_msg = rust_to_gateway_rx.recv() => {
                         if let Ok(json) = mesh_msg {
-                            let _ = ws_stream.send(Message::Text(json.to_string())).await;
+                            let wanted = if json.get("method").and_then(|m| m.as_str()) == Some("mesh:memory:changed") {
+                                let params = json.get("params");
+                                let doc = params.and_then(|p| p.get("docId")).and_then(|d| d.as_str()).unwrap_or("");
+                                let key = params.and_then(|p| p.get("key")).and_then(|k| k.as_str()).unwrap_or("");
+                                subscriptions.iter().any(|(d, prefix)| d == doc && key.starts_with(prefix.as_str()))
+                            } else {
+                                true
+                            };
+                            if wanted {
+                                let _ = ws_stream.send(Message::Text(json.to_string())).await;
+                            }
                         }
                     }
                 }
@@ -96,3 +313,23 @@ pub async fn run_ws_server(
         });
     }
 }
+
+/// Applies a `mesh:text:*` edit. Every parameter the method takes is
+/// required, so a malformed call cannot erase text by defaulting to empty.
+fn edit_text(memory: &MemorySync, method: &str, key: &str, params: Option<&serde_json::Value>) -> Result<(), String> {
+    let param = |name: &str| params.and_then(|p| p.get(name)).ok_or_else(|| format!("missing {}", name));
+    let str_param = |name: &str| param(name)?.as_str().ok_or_else(|| format!("{} must be a string", name));
+    let num_param = |name: &str| {
+        param(name)?
+            .as_u64()
+            .map(|n| n as usize)
+            .ok_or_else(|| format!("{} must be a non-negative integer", name))
+    };
+    match method {
+        "mesh:text:update" => memory.update_text(key, str_param("value")?),
+        "mesh:text:insert" => memory.insert_text_at(key, num_param("index")?, str_param("text")?),
+        "mesh:text:delete" => memory.delete_text(key, num_param("index")?, num_param("length")?),
+        _ => memory.splice_text(key, num_param("index")?, num_param("delete")?, str_param("insert")?),
+    }
+    .map_err(|e| e.to_string())
+}